@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::time::{Duration, Instant};
+
+use crate::follow::FollowStep;
+use crate::motion::{MAX_WHEEL_SPEED, UNITS_PER_SPEED};
+
+/// Difference in wheel speed per radian when turning towards a new velocity
+const TURN_GAIN: f32 = 40.0;
+
+/// Weight of the time to collision against the change in velocity, in mat units
+const COLLISION_WEIGHT: f32 = 100.0;
+
+/// Number of directions and speeds tried when looking for a new velocity
+const DIRECTIONS: usize = 16;
+const SPEEDS: usize = 5;
+
+/// How long a position is used for before it is ignored
+const STALE: Duration = Duration::from_secs(1);
+
+/// Latest position and velocity of a toio in mat units and mat units per second
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Body {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    at: Instant,
+}
+
+/// Keeps track of where every toio on the mat is and how fast it is moving, so
+/// that toios steered from the host can avoid each other with reciprocal
+/// velocity obstacles (RVO). Each toio picks the velocity closest to the one
+/// it wants that does not collide with the others soon, assuming they also
+/// take their share of avoiding it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Swarm {
+    /// Whether toios avoid each other
+    pub enabled: bool,
+    /// Radius of the circle each toio fits in, in mat units
+    pub radius: f32,
+    bodies: HashMap<usize, Body>,
+}
+
+impl Swarm {
+    pub fn new(enabled: bool, radius: f32) -> Swarm {
+        return Swarm {
+            enabled,
+            radius,
+            bodies: HashMap::new(),
+        };
+    }
+
+    /// Records the position of the toio with the given ID in mat units
+    pub fn update(&mut self, id: usize, x: f32, y: f32) {
+        let now = Instant::now();
+        let (mut vx, mut vy) = (0.0, 0.0);
+
+        // smooth the velocity since positions are noisy
+        if let Some(body) = self.bodies.get(&id) {
+            let dt = now.duration_since(body.at).as_secs_f32();
+            if dt > 0.0 && dt < STALE.as_secs_f32() {
+                vx = (body.vx + (x - body.x) / dt) / 2.0;
+                vy = (body.vy + (y - body.y) / dt) / 2.0;
+            }
+        }
+
+        self.bodies.insert(
+            id,
+            Body {
+                x,
+                y,
+                vx,
+                vy,
+                at: now,
+            },
+        );
+    }
+
+    /// Forgets the toio with the given ID, such as when it leaves the mat
+    pub fn remove(&mut self, id: usize) {
+        self.bodies.remove(&id);
+    }
+
+    /// Latest position of every toio on the mat in mat units, by ID
+    pub fn positions(&self) -> Vec<(usize, f32, f32)> {
+        let now = Instant::now();
+        let mut positions: Vec<(usize, f32, f32)> = self
+            .bodies
+            .iter()
+            .filter(|(_, body)| now.duration_since(body.at) < STALE)
+            .map(|(id, body)| (*id, body.x, body.y))
+            .collect();
+        positions.sort_by_key(|(id, _, _)| *id);
+        return positions;
+    }
+
+    /// Adjusts how the toio with the given ID drives, given its angle in
+    /// degrees, so that it does not collide with the other toios
+    pub fn avoid(&self, id: usize, theta: f32, step: FollowStep) -> FollowStep {
+        let FollowStep::Drive { left, right } = step else {
+            return step;
+        };
+        let Some(me) = self.bodies.get(&id).filter(|_| self.enabled) else {
+            return step;
+        };
+
+        let now = Instant::now();
+        let max_speed = MAX_WHEEL_SPEED as f32 * UNITS_PER_SPEED;
+        let reach = 2.0 * self.radius + 2.0 * max_speed;
+        let others: Vec<&Body> = self
+            .bodies
+            .iter()
+            .filter(|(other, body)| {
+                **other != id
+                    && now.duration_since(body.at) < STALE
+                    && (body.x - me.x).hypot(body.y - me.y) < reach
+            })
+            .map(|(_, body)| body)
+            .collect();
+        if others.is_empty() {
+            return step;
+        }
+
+        // velocity the controller wants, ignoring how it turns
+        let heading = theta.to_radians();
+        let forward = (left as f32 + right as f32) / 2.0 * UNITS_PER_SPEED;
+        let preferred = (forward * heading.cos(), forward * heading.sin());
+
+        let candidates = (1..=SPEEDS).flat_map(|speed| {
+            let speed = max_speed * speed as f32 / SPEEDS as f32;
+            (0..DIRECTIONS).map(move |direction| {
+                let angle = 2.0 * PI * direction as f32 / DIRECTIONS as f32;
+                (speed * angle.cos(), speed * angle.sin())
+            })
+        });
+
+        let mut best = (f32::MAX, preferred);
+        for velocity in std::iter::once(preferred)
+            .chain(std::iter::once((0.0, 0.0)))
+            .chain(candidates)
+        {
+            let collision = others
+                .iter()
+                .map(|other| self.time_to_collision(me, other, velocity))
+                .fold(f32::INFINITY, f32::min);
+            let change = (velocity.0 - preferred.0).hypot(velocity.1 - preferred.1);
+            let penalty = COLLISION_WEIGHT / collision + change;
+            if penalty < best.0 {
+                best = (penalty, velocity);
+            }
+        }
+
+        let velocity = best.1;
+        if velocity == preferred {
+            return step;
+        }
+
+        // turn towards the new velocity, driving backwards if it is behind
+        let mut alpha = velocity.1.atan2(velocity.0) - heading;
+        while alpha > PI {
+            alpha -= 2.0 * PI;
+        }
+        while alpha < -PI {
+            alpha += 2.0 * PI;
+        }
+        let direction = if alpha.abs() > FRAC_PI_2 {
+            alpha -= PI.copysign(alpha);
+            -1.0
+        } else {
+            1.0
+        };
+        let speed = velocity.0.hypot(velocity.1) / UNITS_PER_SPEED;
+        let forward = speed * alpha.cos() * direction;
+        let turn = TURN_GAIN * alpha;
+
+        return FollowStep::Drive {
+            left: (forward + turn).round() as i16,
+            right: (forward - turn).round() as i16,
+        };
+    }
+
+    /// Seconds until two toios collide if one takes the given velocity and
+    /// each takes half of the responsibility for avoiding the other
+    fn time_to_collision(&self, me: &Body, other: &Body, velocity: (f32, f32)) -> f32 {
+        let (px, py) = (other.x - me.x, other.y - me.y);
+        let (vx, vy) = (
+            2.0 * velocity.0 - me.vx - other.vx,
+            2.0 * velocity.1 - me.vy - other.vy,
+        );
+        let distance = 2.0 * self.radius;
+
+        let a = vx * vx + vy * vy;
+        let b = px * vx + py * vy;
+        let c = px * px + py * py - distance * distance;
+
+        // already overlapping, which only matters when moving closer
+        if c < 0.0 {
+            return if b > 0.0 { 0.0 } else { f32::INFINITY };
+        }
+
+        // solve |p - v t| = distance for the earliest t
+        let discriminant = b * b - a * c;
+        if a == 0.0 || b <= 0.0 || discriminant < 0.0 {
+            return f32::INFINITY;
+        }
+        return (b - discriminant.sqrt()) / a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swarm(bodies: &[(f32, f32, f32, f32)]) -> Swarm {
+        let mut swarm = Swarm::new(true, 16.0);
+        let at = Instant::now();
+        for (id, (x, y, vx, vy)) in bodies.iter().enumerate() {
+            let (x, y, vx, vy) = (*x, *y, *vx, *vy);
+            swarm.bodies.insert(id, Body { x, y, vx, vy, at });
+        }
+        return swarm;
+    }
+
+    /// Velocity a toio at the given angle takes once it has turned as much
+    /// as the step asks for
+    fn velocity(step: FollowStep, theta: f32) -> (f32, f32) {
+        let FollowStep::Drive { left, right } = step else {
+            panic!("expected to drive, got {:?}", step);
+        };
+        let heading = theta.to_radians() + (left - right) as f32 / (2.0 * TURN_GAIN);
+        let forward = (left + right) as f32 / 2.0 * UNITS_PER_SPEED;
+        return (forward * heading.cos(), forward * heading.sin());
+    }
+
+    #[test]
+    fn leaves_a_clear_path_unchanged() {
+        let step = FollowStep::Drive {
+            left: 50,
+            right: 50,
+        };
+
+        // the other toio is off to the side and not moving
+        let clear = swarm(&[(100.0, 100.0, 100.0, 0.0), (300.0, 300.0, 0.0, 0.0)]);
+        assert_eq!(clear.avoid(0, 0.0, step), step);
+        assert_eq!(
+            clear.avoid(0, 0.0, FollowStep::Arrived),
+            FollowStep::Arrived
+        );
+
+        // nor is anything changed for a toio alone or when avoidance is off
+        let alone = swarm(&[(100.0, 100.0, 100.0, 0.0)]);
+        assert_eq!(alone.avoid(0, 0.0, step), step);
+        let mut disabled = swarm(&[(100.0, 100.0, 100.0, 0.0), (150.0, 100.0, 0.0, 0.0)]);
+        disabled.enabled = false;
+        assert_eq!(disabled.avoid(0, 0.0, step), step);
+    }
+
+    #[test]
+    fn deflects_a_head_on_pair() {
+        let step = FollowStep::Drive {
+            left: 50,
+            right: 50,
+        };
+        let pair = swarm(&[(100.0, 200.0, 100.0, 0.0), (200.0, 200.0, -100.0, 0.0)]);
+
+        // driving straight on, each would collide within a second
+        let (me, other) = (&pair.bodies[&0], &pair.bodies[&1]);
+        assert!(pair.time_to_collision(me, other, (100.0, 0.0)) < 1.0);
+        assert!(pair.time_to_collision(other, me, (-100.0, 0.0)) < 1.0);
+
+        // each turns away so that the collision is further off, and the two
+        // pass on opposite sides of each other
+        let left = velocity(pair.avoid(0, 0.0, step), 0.0);
+        let right = velocity(pair.avoid(1, 180.0, step), 180.0);
+        assert!(pair.time_to_collision(me, other, left) > 1.0, "{:?}", left);
+        assert!(
+            pair.time_to_collision(other, me, right) > 1.0,
+            "{:?}",
+            right
+        );
+        assert!(left.1 * right.1 < 0.0, "{:?} {:?}", left, right);
+    }
+}
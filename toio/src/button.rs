@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep_until;
+
+use crate::toio::{Update, Updates};
+
+/// Gesture made with the button on top of a toio
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonEvent {
+    /// The button was pressed
+    Down,
+    /// The button was released
+    Up,
+    /// The button was pressed and released quickly
+    Click,
+    /// The button has been held down for the long press time
+    LongPress,
+    /// The button was clicked twice within the double click time
+    DoubleClick,
+}
+
+/// How long the button is held for a long press, and how soon a second click
+/// must follow the first for a double click
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ButtonTimings {
+    pub long_press: Duration,
+    pub double_click: Duration,
+}
+
+/// Recognizes gestures from the presses and releases of the button. Every
+/// press and release is reported, along with a click when a press is released
+/// before it becomes a long press. The second click of a double click is
+/// reported as a double click instead of a click.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ButtonGestures {
+    timings: ButtonTimings,
+    pressed: Option<Instant>,
+    long_pressed: bool,
+    clicked: Option<Instant>,
+}
+
+/// Stream of button gestures from the updates of a toio
+pub struct ButtonEvents {
+    updates: Updates,
+    gestures: ButtonGestures,
+    queue: VecDeque<ButtonEvent>,
+}
+
+impl ButtonEvent {
+    /// Name of the gesture, such as `down` or `doubleclick`
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ButtonEvent::Down => "down",
+            ButtonEvent::Up => "up",
+            ButtonEvent::Click => "click",
+            ButtonEvent::LongPress => "longpress",
+            ButtonEvent::DoubleClick => "doubleclick",
+        };
+    }
+}
+
+impl Default for ButtonTimings {
+    fn default() -> ButtonTimings {
+        return ButtonTimings {
+            long_press: Duration::from_millis(500),
+            double_click: Duration::from_millis(300),
+        };
+    }
+}
+
+impl ButtonGestures {
+    pub fn new(timings: ButtonTimings) -> ButtonGestures {
+        return ButtonGestures {
+            timings,
+            pressed: None,
+            long_pressed: false,
+            clicked: None,
+        };
+    }
+
+    /// Records the button being pressed or released, returning the gestures
+    /// it completes
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Vec<ButtonEvent> {
+        let mut events = self.poll(now);
+
+        match (pressed, self.pressed) {
+            (true, None) => {
+                self.pressed = Some(now);
+                self.long_pressed = false;
+                events.push(ButtonEvent::Down);
+            }
+            (false, Some(_)) => {
+                self.pressed = None;
+                events.push(ButtonEvent::Up);
+                if !self.long_pressed {
+                    let double = self.clicked.is_some_and(|clicked| {
+                        now.duration_since(clicked) <= self.timings.double_click
+                    });
+                    if double {
+                        self.clicked = None;
+                        events.push(ButtonEvent::DoubleClick);
+                    } else {
+                        self.clicked = Some(now);
+                        events.push(ButtonEvent::Click);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        return events;
+    }
+
+    /// Returns a long press once the button has been held for long enough
+    pub fn poll(&mut self, now: Instant) -> Vec<ButtonEvent> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.long_pressed = true;
+                self.clicked = None;
+                return vec![ButtonEvent::LongPress];
+            }
+            _ => return vec![],
+        }
+    }
+
+    /// When the button being held will become a long press
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.long_pressed {
+            return None;
+        }
+        return self
+            .pressed
+            .map(|pressed| pressed + self.timings.long_press);
+    }
+}
+
+impl Updates {
+    /// Turns the updates into a stream of button gestures, ignoring updates
+    /// other than the button
+    pub fn button_events(self, timings: ButtonTimings) -> ButtonEvents {
+        return ButtonEvents {
+            updates: self,
+            gestures: ButtonGestures::new(timings),
+            queue: VecDeque::new(),
+        };
+    }
+}
+
+impl ButtonEvents {
+    /// Waits for the next button gesture
+    pub async fn next(&mut self) -> Option<ButtonEvent> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Some(event);
+            }
+
+            let events = match self.gestures.deadline() {
+                Some(deadline) => tokio::select! {
+                    update = self.updates.next() => self.gestures_for(update?),
+                    _ = sleep_until(deadline.into()) => self.gestures.poll(Instant::now()),
+                },
+                None => {
+                    let update = self.updates.next().await?;
+                    self.gestures_for(update)
+                }
+            };
+            self.queue.extend(events);
+        }
+    }
+
+    fn gestures_for(&mut self, update: Update) -> Vec<ButtonEvent> {
+        return match update {
+            Update::Button { pressed } => self.gestures.update(pressed, Instant::now()),
+            _ => vec![],
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_clicks_and_long_presses() {
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+        let mut gestures = ButtonGestures::new(ButtonTimings::default());
+
+        assert_eq!(gestures.update(true, ms(0)), vec![ButtonEvent::Down]);
+        assert_eq!(
+            gestures.update(false, ms(100)),
+            vec![ButtonEvent::Up, ButtonEvent::Click]
+        );
+        gestures.update(true, ms(200));
+        assert_eq!(
+            gestures.update(false, ms(250)),
+            vec![ButtonEvent::Up, ButtonEvent::DoubleClick]
+        );
+
+        gestures.update(true, ms(1000));
+        assert_eq!(gestures.deadline(), Some(ms(1500)));
+        assert!(gestures.poll(ms(1400)).is_empty());
+        assert_eq!(gestures.poll(ms(1500)), vec![ButtonEvent::LongPress]);
+        assert_eq!(gestures.deadline(), None);
+        assert_eq!(gestures.update(false, ms(1600)), vec![ButtonEvent::Up]);
+    }
+}
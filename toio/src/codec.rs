@@ -0,0 +1,1353 @@
+use std::error::Error;
+use std::fmt;
+
+use btleplug::api::WriteType;
+use uuid::Uuid;
+
+use crate::toio::*;
+
+pub const SERVICE: Uuid = Uuid::from_u128(0x10B20100_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const POSITION: Uuid = Uuid::from_u128(0x10B20101_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const MOTOR: Uuid = Uuid::from_u128(0x10B20102_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const LIGHT: Uuid = Uuid::from_u128(0x10B20103_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const SOUND: Uuid = Uuid::from_u128(0x10B20104_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const MOTION: Uuid = Uuid::from_u128(0x10B20106_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const BUTTON: Uuid = Uuid::from_u128(0x10B20107_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const BATTERY: Uuid = Uuid::from_u128(0x10B20108_5B3B_4571_9508_CF3EFCD7BBAE);
+pub const CONFIG: Uuid = Uuid::from_u128(0x10B201FF_5B3B_4571_9508_CF3EFCD7BBAE);
+
+/// Most targets the firmware accepts in one MultiTarget packet
+pub const MAX_TARGETS: usize = 29;
+
+/// Error from decoding the bytes of a characteristic, holding the raw bytes
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The bytes end before every field of the packet has been read
+    Truncated { uuid: Uuid, bytes: Vec<u8> },
+    /// The packet type, or a fixed byte within the packet, is not in the spec
+    Unknown { uuid: Uuid, bytes: Vec<u8> },
+}
+
+impl DecodeError {
+    pub fn uuid(&self) -> Uuid {
+        return match self {
+            DecodeError::Truncated { uuid, .. } | DecodeError::Unknown { uuid, .. } => *uuid,
+        };
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        return match self {
+            DecodeError::Truncated { bytes, .. } | DecodeError::Unknown { bytes, .. } => bytes,
+        };
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            DecodeError::Truncated { .. } => "Truncated",
+            DecodeError::Unknown { .. } => "Unknown",
+        };
+        write!(
+            f,
+            "{} {} Update: {:?}",
+            kind,
+            uuid_to_string(self.uuid()),
+            self.bytes()
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Reads little endian fields from the bytes of a characteristic
+struct Reader<'a> {
+    uuid: Uuid,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(uuid: Uuid, bytes: &'a [u8]) -> Reader<'a> {
+        return Reader {
+            uuid,
+            bytes,
+            pos: 0,
+        };
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let field = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| self.truncated())?;
+        self.pos += N;
+        return Ok(field.try_into().unwrap());
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        return Ok(self.take::<1>()?[0]);
+    }
+
+    fn i8(&mut self) -> Result<i8, DecodeError> {
+        return Ok(i8::from_le_bytes(self.take()?));
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        return Ok(u16::from_le_bytes(self.take()?));
+    }
+
+    fn i16(&mut self) -> Result<i16, DecodeError> {
+        return Ok(i16::from_le_bytes(self.take()?));
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        return Ok(u32::from_le_bytes(self.take()?));
+    }
+
+    fn f32(&mut self) -> Result<f32, DecodeError> {
+        return Ok(f32::from_le_bytes(self.take()?));
+    }
+
+    /// Reads the angle of a target, which must have a known angle mode
+    fn angle(&mut self) -> Result<TargetAngle, DecodeError> {
+        let theta = self.u16()?;
+        return unpack_angle(theta).ok_or_else(|| self.unknown());
+    }
+
+    /// Reads a byte that must have a fixed value
+    fn expect(&mut self, byte: u8) -> Result<(), DecodeError> {
+        if self.u8()? != byte {
+            return Err(self.unknown());
+        }
+        return Ok(());
+    }
+
+    /// Number of bytes that have not been read
+    fn remaining(&self) -> usize {
+        return self.bytes.len().saturating_sub(self.pos);
+    }
+
+    fn truncated(&self) -> DecodeError {
+        return DecodeError::Truncated {
+            uuid: self.uuid,
+            bytes: self.bytes.to_vec(),
+        };
+    }
+
+    fn unknown(&self) -> DecodeError {
+        return DecodeError::Unknown {
+            uuid: self.uuid,
+            bytes: self.bytes.to_vec(),
+        };
+    }
+}
+
+/// Encodes a command into the characteristic it is written to, its bytes,
+/// and whether the write expects a response, following the toio spec at
+/// https://toio.github.io/toio-spec/en/docs/about
+pub fn encode(command: Command) -> (Uuid, Vec<u8>, WriteType) {
+    let uuid = match command {
+        Command::MotionRequest | Command::MagneticRequest | Command::PostureRequest { .. } => {
+            MOTION
+        }
+        Command::MotorControl { .. }
+        | Command::MotorDuration { .. }
+        | Command::MotorTarget { .. }
+        | Command::MultiTarget { .. }
+        | Command::MotorAcceleration { .. } => MOTOR,
+        Command::LedOff | Command::Led { .. } | Command::MultiLed { .. } => LIGHT,
+        Command::SoundOff | Command::Sound { .. } | Command::Midi { .. } => SOUND,
+        Command::MotorSpeedConfig { .. } => CONFIG,
+    };
+
+    let write_type = match uuid {
+        LIGHT | SOUND | CONFIG => WriteType::WithResponse,
+        _ => WriteType::WithoutResponse,
+    };
+
+    let cmd: Vec<u8> = match command {
+        Command::MotionRequest => {
+            vec![0x81]
+        }
+        Command::MagneticRequest => {
+            vec![0x82]
+        }
+        Command::PostureRequest { format } => {
+            vec![0x83, format]
+        }
+        Command::MotorControl {
+            left_direction,
+            left_speed,
+            right_direction,
+            right_speed,
+        } => {
+            vec![
+                0x01,
+                0x01,
+                left_direction,
+                left_speed,
+                0x02,
+                right_direction,
+                right_speed,
+            ]
+        }
+        Command::MotorDuration {
+            left_direction,
+            left_speed,
+            right_direction,
+            right_speed,
+            duration,
+        } => {
+            vec![
+                0x02,
+                0x01,
+                left_direction,
+                left_speed,
+                0x02,
+                right_direction,
+                right_speed,
+                duration,
+            ]
+        }
+        Command::MotorTarget {
+            control,
+            timeout,
+            move_type,
+            max_speed,
+            speed_change,
+            x_target,
+            y_target,
+            theta_target,
+        } => {
+            vec![
+                0x03,
+                control,
+                timeout,
+                move_type,
+                max_speed,
+                speed_change,
+                0x00,
+                (x_target & 0x00FF) as u8,
+                ((x_target & 0xFF00) >> 8) as u8,
+                (y_target & 0x00FF) as u8,
+                ((y_target & 0xFF00) >> 8) as u8,
+                (pack_angle(theta_target) & 0x00FF) as u8,
+                ((pack_angle(theta_target) & 0xFF00) >> 8) as u8,
+            ]
+        }
+        Command::MultiTarget {
+            control,
+            timeout,
+            move_type,
+            max_speed,
+            speed_change,
+            op_add,
+            targets,
+        } => {
+            let mut cmd = vec![
+                0x04,
+                control,
+                timeout,
+                move_type,
+                max_speed,
+                speed_change,
+                0x00,
+                match op_add {
+                    WriteMode::Overwrite => 0x00,
+                    WriteMode::Append => 0x01,
+                },
+            ];
+            cmd.append(&mut parse_target_command(targets));
+            cmd
+        }
+        Command::MotorAcceleration {
+            velocity,
+            acceleration,
+            rotational_velocity,
+            rotational_direction,
+            direction,
+            priority,
+            duration,
+        } => {
+            vec![
+                0x05,
+                velocity,
+                acceleration,
+                (rotational_velocity & 0x00FF) as u8,
+                ((rotational_velocity & 0xFF00) >> 8) as u8,
+                rotational_direction,
+                direction,
+                priority,
+                duration,
+            ]
+        }
+        Command::LedOff => {
+            vec![0x01]
+        }
+        Command::Led {
+            duration,
+            red,
+            green,
+            blue,
+        } => {
+            vec![0x03, duration, 0x01, 0x01, red, green, blue]
+        }
+        Command::MultiLed {
+            repetitions,
+            lights,
+        } => parse_led_command(repetitions, lights),
+        Command::SoundOff => {
+            vec![0x01]
+        }
+        Command::Sound {
+            sound_effect,
+            volume,
+        } => {
+            vec![
+                0x02,         //sound
+                sound_effect, //sound effect ID
+                volume,       //volume
+            ]
+        }
+        Command::Midi { repetitions, notes } => parse_midi_command(repetitions, notes),
+        Command::MotorSpeedConfig { enabled } => {
+            vec![0x1c, 0x00, enabled as u8]
+        }
+    };
+
+    return (uuid, cmd, write_type);
+}
+
+/// Decodes a notification from a characteristic into an update
+pub fn decode(uuid: Uuid, bytes: &[u8]) -> Result<Update, DecodeError> {
+    let mut reader = Reader::new(uuid, bytes);
+
+    return match uuid {
+        POSITION => match reader.u8()? {
+            0x01 => Ok(Update::Position {
+                x_center: reader.u16()?,
+                y_center: reader.u16()?,
+                theta: reader.u16()?,
+                x_sensor: reader.u16()?,
+                y_sensor: reader.u16()?,
+            }),
+            0x02 => Ok(Update::Standard {
+                standard: reader.u32()?,
+                theta: reader.u16()?,
+            }),
+            0x03 => Ok(Update::PositionMissed),
+            0x04 => Ok(Update::StandardMissed),
+            _ => Err(reader.unknown()),
+        },
+        MOTOR => match reader.u8()? {
+            0x83 => Ok(Update::MotorTargetResponse {
+                control: reader.u8()?,
+                response: reader.u8()?,
+            }),
+            0x84 => Ok(Update::MultiTargetResponse {
+                control: reader.u8()?,
+                response: reader.u8()?,
+            }),
+            0xe0 => Ok(Update::MotorSpeed {
+                left_speed: reader.u8()?,
+                right_speed: reader.u8()?,
+            }),
+            _ => Err(reader.unknown()),
+        },
+        MOTION => match reader.u8()? {
+            0x01 => Ok(Update::Motion {
+                horizontal: reader.u8()?,
+                collision: reader.u8()?,
+                double_tap: reader.u8()?,
+                posture: reader.u8()?,
+                // firmware before 2.1.0 does not send the shake level
+                shake: match reader.remaining() {
+                    0 => 0,
+                    _ => reader.u8()?,
+                },
+            }),
+            0x02 => Ok(Update::Magnetic {
+                state: reader.u8()?,
+                strength: reader.u8()?,
+                forcex: reader.i8()?,
+                forcey: reader.i8()?,
+                forcez: reader.i8()?,
+            }),
+            0x03 => match reader.u8()? {
+                0x01 => Ok(Update::PostureEuler {
+                    roll: reader.i16()?,
+                    pitch: reader.i16()?,
+                    yaw: reader.i16()?,
+                }),
+                0x02 => Ok(Update::PostureQuaternion {
+                    w: reader.f32()?,
+                    x: reader.f32()?,
+                    y: reader.f32()?,
+                    z: reader.f32()?,
+                }),
+                0x03 => Ok(Update::PostureHighPrecisionEuler {
+                    roll: reader.f32()?,
+                    pitch: reader.f32()?,
+                    yaw: reader.f32()?,
+                }),
+                _ => Err(reader.unknown()),
+            },
+            _ => Err(reader.unknown()),
+        },
+        BATTERY => Ok(Update::Battery {
+            level: reader.u8()?,
+        }),
+        BUTTON => match reader.u8()? {
+            0x01 => Ok(Update::Button {
+                pressed: reader.u8()? == 0x80,
+            }),
+            _ => Err(reader.unknown()),
+        },
+        CONFIG => match reader.u8()? {
+            0x9c => {
+                reader.expect(0x00)?;
+                Ok(Update::MotorSpeedConfigResponse {
+                    response: reader.u8()?,
+                })
+            }
+            _ => Err(reader.unknown()),
+        },
+        _ => Err(reader.unknown()),
+    };
+}
+
+/// Decodes the bytes written to a characteristic back into a command, the
+/// inverse of `encode`
+pub fn decode_command(uuid: Uuid, bytes: &[u8]) -> Result<Command, DecodeError> {
+    let mut reader = Reader::new(uuid, bytes);
+
+    let command = match uuid {
+        MOTION => match reader.u8()? {
+            0x81 => Command::MotionRequest,
+            0x82 => Command::MagneticRequest,
+            0x83 => Command::PostureRequest {
+                format: reader.u8()?,
+            },
+            _ => return Err(reader.unknown()),
+        },
+        MOTOR => match reader.u8()? {
+            0x01 => {
+                reader.expect(0x01)?;
+                let (left_direction, left_speed) = (reader.u8()?, reader.u8()?);
+                reader.expect(0x02)?;
+                Command::MotorControl {
+                    left_direction,
+                    left_speed,
+                    right_direction: reader.u8()?,
+                    right_speed: reader.u8()?,
+                }
+            }
+            0x02 => {
+                reader.expect(0x01)?;
+                let (left_direction, left_speed) = (reader.u8()?, reader.u8()?);
+                reader.expect(0x02)?;
+                Command::MotorDuration {
+                    left_direction,
+                    left_speed,
+                    right_direction: reader.u8()?,
+                    right_speed: reader.u8()?,
+                    duration: reader.u8()?,
+                }
+            }
+            0x03 => {
+                let (control, timeout, move_type, max_speed, speed_change) = (
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                );
+                reader.expect(0x00)?;
+                Command::MotorTarget {
+                    control,
+                    timeout,
+                    move_type,
+                    max_speed,
+                    speed_change,
+                    x_target: reader.u16()?,
+                    y_target: reader.u16()?,
+                    theta_target: reader.angle()?,
+                }
+            }
+            0x04 => {
+                let (control, timeout, move_type, max_speed, speed_change) = (
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                    reader.u8()?,
+                );
+                reader.expect(0x00)?;
+                let op_add = match reader.u8()? {
+                    0x00 => WriteMode::Overwrite,
+                    0x01 => WriteMode::Append,
+                    _ => return Err(reader.unknown()),
+                };
+
+                let mut targets = vec![];
+                while reader.remaining() > 0 {
+                    targets.push(TargetCommand {
+                        x_target: reader.u16()?,
+                        y_target: reader.u16()?,
+                        theta_target: reader.angle()?,
+                    });
+                }
+
+                Command::MultiTarget {
+                    control,
+                    timeout,
+                    move_type,
+                    max_speed,
+                    speed_change,
+                    op_add,
+                    targets,
+                }
+            }
+            0x05 => Command::MotorAcceleration {
+                velocity: reader.u8()?,
+                acceleration: reader.u8()?,
+                rotational_velocity: reader.u16()?,
+                rotational_direction: reader.u8()?,
+                direction: reader.u8()?,
+                priority: reader.u8()?,
+                duration: reader.u8()?,
+            },
+            _ => return Err(reader.unknown()),
+        },
+        LIGHT => match reader.u8()? {
+            0x01 => Command::LedOff,
+            0x03 => {
+                let duration = reader.u8()?;
+                reader.expect(0x01)?;
+                reader.expect(0x01)?;
+                Command::Led {
+                    duration,
+                    red: reader.u8()?,
+                    green: reader.u8()?,
+                    blue: reader.u8()?,
+                }
+            }
+            0x04 => {
+                let repetitions = reader.u8()?;
+                let count = reader.u8()?;
+
+                let mut lights = vec![];
+                for _ in 0..count {
+                    let duration = reader.u8()?;
+                    reader.expect(0x01)?;
+                    reader.expect(0x01)?;
+                    lights.push(LedCommand {
+                        duration,
+                        red: reader.u8()?,
+                        green: reader.u8()?,
+                        blue: reader.u8()?,
+                    });
+                }
+
+                Command::MultiLed {
+                    repetitions,
+                    lights,
+                }
+            }
+            _ => return Err(reader.unknown()),
+        },
+        SOUND => match reader.u8()? {
+            0x01 => Command::SoundOff,
+            0x02 => Command::Sound {
+                sound_effect: reader.u8()?,
+                volume: reader.u8()?,
+            },
+            0x03 => {
+                let repetitions = reader.u8()?;
+                let count = reader.u8()?;
+
+                let mut notes = vec![];
+                for _ in 0..count {
+                    notes.push(MidiCommand {
+                        duration: reader.u8()?,
+                        note: reader.u8()?,
+                        volume: reader.u8()?,
+                    });
+                }
+
+                Command::Midi { repetitions, notes }
+            }
+            _ => return Err(reader.unknown()),
+        },
+        CONFIG => match reader.u8()? {
+            0x1c => {
+                reader.expect(0x00)?;
+                match reader.u8()? {
+                    0x00 => Command::MotorSpeedConfig { enabled: false },
+                    0x01 => Command::MotorSpeedConfig { enabled: true },
+                    _ => return Err(reader.unknown()),
+                }
+            }
+            _ => return Err(reader.unknown()),
+        },
+        _ => return Err(reader.unknown()),
+    };
+
+    // trailing bytes mean the packet is not the command it looks like
+    if reader.remaining() > 0 {
+        return Err(reader.unknown());
+    }
+
+    return Ok(command);
+}
+
+/// matches UUIDs of toios a string of their coresponding service
+pub fn uuid_to_string(uuid: Uuid) -> String {
+    return match uuid {
+        SERVICE => "Service",
+        POSITION => "Position",
+        MOTOR => "Motor",
+        LIGHT => "Light",
+        SOUND => "Sound",
+        MOTION => "Motion",
+        BUTTON => "Button",
+        BATTERY => "Battery",
+        CONFIG => "Config",
+        _ => "",
+    }
+    .to_owned();
+}
+
+/// Packs the angle of a target into 16 bits, where the top 3 bits are the angle
+/// mode and the rest are the angle in degrees. Absolute angles are wrapped to
+/// 0 to 359 degrees.
+pub fn pack_angle(angle: TargetAngle) -> u16 {
+    let (mode, degrees): (u16, u16) = match angle {
+        TargetAngle::Absolute(degrees) => (0x00, degrees % 360),
+        TargetAngle::AbsolutePositive(degrees) => (0x01, degrees % 360),
+        TargetAngle::AbsoluteNegative(degrees) => (0x02, degrees % 360),
+        TargetAngle::Relative(degrees) if degrees >= 0 => (0x03, degrees as u16),
+        TargetAngle::Relative(degrees) => (0x04, degrees.unsigned_abs()),
+        TargetAngle::Unchanged => (0x05, 0),
+        TargetAngle::Keep => (0x06, 0),
+    };
+    return (mode << 13) | (degrees & 0x1FFF);
+}
+
+/// Unpacks the angle of a target from 16 bits, if the angle mode is known
+pub fn unpack_angle(theta: u16) -> Option<TargetAngle> {
+    let degrees = theta & 0x1FFF;
+    return match theta >> 13 {
+        0x00 => Some(TargetAngle::Absolute(degrees)),
+        0x01 => Some(TargetAngle::AbsolutePositive(degrees)),
+        0x02 => Some(TargetAngle::AbsoluteNegative(degrees)),
+        0x03 => Some(TargetAngle::Relative(degrees as i16)),
+        0x04 => Some(TargetAngle::Relative(-(degrees as i16))),
+        0x05 => Some(TargetAngle::Unchanged),
+        0x06 => Some(TargetAngle::Keep),
+        _ => None,
+    };
+}
+
+/// Splits a MultiTarget command with more than [`MAX_TARGETS`] targets into
+/// commands that each fit in one packet. The first keeps the write mode of the
+/// command and the rest are appended, each with the next control ID. Any other
+/// command is returned as is.
+pub fn split_multi_target(command: Command) -> Vec<Command> {
+    let Command::MultiTarget {
+        control,
+        timeout,
+        move_type,
+        max_speed,
+        speed_change,
+        op_add,
+        targets,
+    } = command
+    else {
+        return vec![command];
+    };
+
+    if targets.len() <= MAX_TARGETS {
+        return vec![Command::MultiTarget {
+            control,
+            timeout,
+            move_type,
+            max_speed,
+            speed_change,
+            op_add,
+            targets,
+        }];
+    }
+
+    return targets
+        .chunks(MAX_TARGETS)
+        .enumerate()
+        .map(|(i, targets)| Command::MultiTarget {
+            control: control.wrapping_add(i as u8),
+            timeout,
+            move_type,
+            max_speed,
+            speed_change,
+            op_add: if i == 0 { op_add } else { WriteMode::Append },
+            targets: targets.to_vec(),
+        })
+        .collect();
+}
+
+fn parse_target_command(vals: Vec<TargetCommand>) -> Vec<u8> {
+    let mut cmd = vec![];
+
+    for target in vals.iter() {
+        cmd.push((target.x_target & 0x00FF) as u8);
+        cmd.push(((target.x_target & 0xFF00) >> 8) as u8);
+        cmd.push((target.y_target & 0x00FF) as u8);
+        cmd.push(((target.y_target & 0xFF00) >> 8) as u8);
+        cmd.push((pack_angle(target.theta_target) & 0x00FF) as u8);
+        cmd.push(((pack_angle(target.theta_target) & 0xFF00) >> 8) as u8);
+    }
+
+    return cmd;
+}
+
+fn parse_led_command(repetitions: u8, vals: Vec<LedCommand>) -> Vec<u8> {
+    let mut cmd = vec![0x04, repetitions, vals.len() as u8];
+
+    for led in vals.iter() {
+        cmd.push(led.duration);
+        cmd.push(0x01);
+        cmd.push(0x01);
+        cmd.push(led.red);
+        cmd.push(led.green);
+        cmd.push(led.blue);
+    }
+
+    return cmd;
+}
+
+fn parse_midi_command(repetitions: u8, vals: Vec<MidiCommand>) -> Vec<u8> {
+    let mut cmd = vec![0x03, repetitions, vals.len() as u8];
+
+    for note in vals.iter() {
+        cmd.push(note.duration);
+        cmd.push(note.note);
+        cmd.push(note.volume);
+    }
+
+    return cmd;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every command with distinct values in each field, and the bytes the
+    /// toio spec expects for it
+    fn commands() -> Vec<(Command, Uuid, Vec<u8>, WriteType)> {
+        return vec![
+            (
+                Command::MotionRequest,
+                MOTION,
+                vec![0x81],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MagneticRequest,
+                MOTION,
+                vec![0x82],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::PostureRequest { format: 0x01 },
+                MOTION,
+                vec![0x83, 0x01],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MotorControl {
+                    left_direction: 0x01,
+                    left_speed: 0x64,
+                    right_direction: 0x02,
+                    right_speed: 0x14,
+                },
+                MOTOR,
+                vec![0x01, 0x01, 0x01, 0x64, 0x02, 0x02, 0x14],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MotorDuration {
+                    left_direction: 0x01,
+                    left_speed: 0x64,
+                    right_direction: 0x02,
+                    right_speed: 0x14,
+                    duration: 0xa0,
+                },
+                MOTOR,
+                vec![0x02, 0x01, 0x01, 0x64, 0x02, 0x02, 0x14, 0xa0],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MotorTarget {
+                    control: 0x00,
+                    timeout: 0x05,
+                    move_type: 0x00,
+                    max_speed: 0x50,
+                    speed_change: 0x00,
+                    x_target: 0x012c,
+                    y_target: 0x00a0,
+                    theta_target: TargetAngle::AbsoluteNegative(90),
+                },
+                MOTOR,
+                vec![
+                    0x03, 0x00, 0x05, 0x00, 0x50, 0x00, 0x00, 0x2c, 0x01, 0xa0, 0x00, 0x5a, 0x40,
+                ],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MultiTarget {
+                    control: 0x07,
+                    timeout: 0x05,
+                    move_type: 0x01,
+                    max_speed: 0x50,
+                    speed_change: 0x02,
+                    op_add: WriteMode::Append,
+                    targets: vec![
+                        TargetCommand {
+                            x_target: 0x00fa,
+                            y_target: 0x00fb,
+                            theta_target: TargetAngle::Relative(90),
+                        },
+                        TargetCommand {
+                            x_target: 0x012c,
+                            y_target: 0x012d,
+                            theta_target: TargetAngle::Absolute(180),
+                        },
+                    ],
+                },
+                MOTOR,
+                vec![
+                    0x04, 0x07, 0x05, 0x01, 0x50, 0x02, 0x00, 0x01, 0xfa, 0x00, 0xfb, 0x00, 0x5a,
+                    0x60, 0x2c, 0x01, 0x2d, 0x01, 0xb4, 0x00,
+                ],
+                WriteType::WithoutResponse,
+            ),
+            (
+                Command::MotorAcceleration {
+                    velocity: 0x32,
+                    acceleration: 0x0f,
+                    rotational_velocity: 0x011e,
+                    rotational_direction: 0x01,
+                    direction: 0x00,
+                    priority: 0x01,
+                    duration: 0x64,
+                },
+                MOTOR,
+                vec![0x05, 0x32, 0x0f, 0x1e, 0x01, 0x01, 0x00, 0x01, 0x64],
+                WriteType::WithoutResponse,
+            ),
+            (Command::LedOff, LIGHT, vec![0x01], WriteType::WithResponse),
+            (
+                Command::Led {
+                    duration: 0x10,
+                    red: 0xff,
+                    green: 0x20,
+                    blue: 0x30,
+                },
+                LIGHT,
+                vec![0x03, 0x10, 0x01, 0x01, 0xff, 0x20, 0x30],
+                WriteType::WithResponse,
+            ),
+            (
+                Command::MultiLed {
+                    repetitions: 0x03,
+                    lights: vec![
+                        LedCommand {
+                            duration: 0x1e,
+                            red: 0x00,
+                            green: 0xff,
+                            blue: 0x10,
+                        },
+                        LedCommand {
+                            duration: 0x1f,
+                            red: 0x20,
+                            green: 0x00,
+                            blue: 0xff,
+                        },
+                    ],
+                },
+                LIGHT,
+                vec![
+                    0x04, 0x03, 0x02, 0x1e, 0x01, 0x01, 0x00, 0xff, 0x10, 0x1f, 0x01, 0x01, 0x20,
+                    0x00, 0xff,
+                ],
+                WriteType::WithResponse,
+            ),
+            (
+                Command::SoundOff,
+                SOUND,
+                vec![0x01],
+                WriteType::WithResponse,
+            ),
+            (
+                Command::Sound {
+                    sound_effect: 0x02,
+                    volume: 0xff,
+                },
+                SOUND,
+                vec![0x02, 0x02, 0xff],
+                WriteType::WithResponse,
+            ),
+            (
+                Command::Midi {
+                    repetitions: 0x01,
+                    notes: vec![
+                        MidiCommand {
+                            duration: 0x1e,
+                            note: 0x3c,
+                            volume: 0xff,
+                        },
+                        MidiCommand {
+                            duration: 0x1f,
+                            note: 0x80,
+                            volume: 0x40,
+                        },
+                    ],
+                },
+                SOUND,
+                vec![0x03, 0x01, 0x02, 0x1e, 0x3c, 0xff, 0x1f, 0x80, 0x40],
+                WriteType::WithResponse,
+            ),
+            (
+                Command::MotorSpeedConfig { enabled: true },
+                CONFIG,
+                vec![0x1c, 0x00, 0x01],
+                WriteType::WithResponse,
+            ),
+        ];
+    }
+
+    /// Every update with distinct values in each field, and the bytes the
+    /// toio spec sends for it
+    fn updates() -> Vec<(Uuid, Vec<u8>, Update)> {
+        return vec![
+            (
+                POSITION,
+                vec![
+                    0x01, 0xc5, 0x02, 0x7f, 0x01, 0x32, 0x01, 0xbc, 0x02, 0x82, 0x01,
+                ],
+                Update::Position {
+                    x_center: 709,
+                    y_center: 383,
+                    theta: 306,
+                    x_sensor: 700,
+                    y_sensor: 386,
+                },
+            ),
+            (
+                POSITION,
+                vec![0x02, 0x00, 0x00, 0x38, 0x00, 0xb4, 0x00],
+                Update::Standard {
+                    standard: 3670016,
+                    theta: 180,
+                },
+            ),
+            (POSITION, vec![0x03], Update::PositionMissed),
+            (POSITION, vec![0x04], Update::StandardMissed),
+            (
+                MOTOR,
+                vec![0x83, 0x05, 0x01],
+                Update::MotorTargetResponse {
+                    control: 5,
+                    response: 1,
+                },
+            ),
+            (
+                MOTOR,
+                vec![0x84, 0x06, 0x07],
+                Update::MultiTargetResponse {
+                    control: 6,
+                    response: 7,
+                },
+            ),
+            (
+                MOTOR,
+                vec![0xe0, 0x0a, 0x73],
+                Update::MotorSpeed {
+                    left_speed: 10,
+                    right_speed: 115,
+                },
+            ),
+            (
+                CONFIG,
+                vec![0x9c, 0x00, 0x01],
+                Update::MotorSpeedConfigResponse { response: 1 },
+            ),
+            (
+                MOTION,
+                vec![0x01, 0x01, 0x00, 0x01, 0x05, 0x03],
+                Update::Motion {
+                    horizontal: 1,
+                    collision: 0,
+                    double_tap: 1,
+                    posture: 5,
+                    shake: 3,
+                },
+            ),
+            (
+                MOTION,
+                vec![0x02, 0x01, 0x0a, 0x05, 0xfb, 0x00],
+                Update::Magnetic {
+                    state: 1,
+                    strength: 10,
+                    forcex: 5,
+                    forcey: -5,
+                    forcez: 0,
+                },
+            ),
+            (
+                MOTION,
+                vec![0x03, 0x01, 0xff, 0xff, 0x0a, 0x00, 0x4c, 0xff],
+                Update::PostureEuler {
+                    roll: -1,
+                    pitch: 10,
+                    yaw: -180,
+                },
+            ),
+            (
+                MOTION,
+                vec![
+                    0x03, 0x02, 0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0xbf, 0x00, 0x00, 0x40, 0x40,
+                ],
+                Update::PostureQuaternion {
+                    w: 1.0,
+                    x: 0.0,
+                    y: -0.5,
+                    z: 3.0,
+                },
+            ),
+            (
+                MOTION,
+                vec![
+                    0x03, 0x03, 0x00, 0x00, 0xb4, 0x42, 0x00, 0x00, 0x20, 0xc1, 0x00, 0x00, 0x00,
+                    0x00,
+                ],
+                Update::PostureHighPrecisionEuler {
+                    roll: 90.0,
+                    pitch: -10.0,
+                    yaw: 0.0,
+                },
+            ),
+            (BUTTON, vec![0x01, 0x80], Update::Button { pressed: true }),
+            (BUTTON, vec![0x01, 0x00], Update::Button { pressed: false }),
+            (BATTERY, vec![0x50], Update::Battery { level: 80 }),
+        ];
+    }
+
+    #[test]
+    fn encodes_commands_to_spec_bytes() {
+        for (command, uuid, bytes, write_type) in commands() {
+            let (encoded_uuid, encoded, encoded_write_type) = encode(command.clone());
+            assert_eq!(encoded_uuid, uuid, "{:?}", command);
+            assert_eq!(encoded, bytes, "{:?}", command);
+            assert_eq!(encoded_write_type, write_type, "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn decodes_commands_from_spec_bytes() {
+        for (command, uuid, bytes, _) in commands() {
+            assert_eq!(decode_command(uuid, &bytes), Ok(command));
+        }
+    }
+
+    #[test]
+    fn round_trips_commands() {
+        for (command, _, _, _) in commands() {
+            let (uuid, bytes, _) = encode(command.clone());
+            assert_eq!(decode_command(uuid, &bytes), Ok(command));
+        }
+    }
+
+    #[test]
+    fn round_trips_empty_lists() {
+        let commands = vec![
+            Command::MultiTarget {
+                control: 1,
+                timeout: 2,
+                move_type: 3,
+                max_speed: 4,
+                speed_change: 5,
+                op_add: WriteMode::Overwrite,
+                targets: vec![],
+            },
+            Command::MultiLed {
+                repetitions: 0,
+                lights: vec![],
+            },
+            Command::Midi {
+                repetitions: 0,
+                notes: vec![],
+            },
+        ];
+
+        for command in commands {
+            let (uuid, bytes, _) = encode(command.clone());
+            assert_eq!(decode_command(uuid, &bytes), Ok(command));
+        }
+    }
+
+    #[test]
+    fn encodes_little_endian_targets() {
+        let (_, bytes, _) = encode(Command::MotorTarget {
+            control: 0,
+            timeout: 0,
+            move_type: 0,
+            max_speed: 0,
+            speed_change: 0,
+            x_target: 0xffff,
+            y_target: 0x0100,
+            theta_target: TargetAngle::Absolute(0x00ff),
+        });
+        assert_eq!(bytes[7..], [0xff, 0xff, 0x00, 0x01, 0xff, 0x00]);
+    }
+
+    #[test]
+    fn packs_angle_modes() {
+        let angles = [
+            (TargetAngle::Absolute(90), 0x005a),
+            (TargetAngle::AbsolutePositive(90), 0x205a),
+            (TargetAngle::AbsoluteNegative(90), 0x405a),
+            (TargetAngle::Relative(90), 0x605a),
+            (TargetAngle::Relative(-90), 0x805a),
+            (TargetAngle::Unchanged, 0xa000),
+            (TargetAngle::Keep, 0xc000),
+        ];
+        for (angle, theta) in angles {
+            assert_eq!(pack_angle(angle), theta);
+            assert_eq!(unpack_angle(theta), Some(angle));
+        }
+        assert_eq!(unpack_angle(0xe000), None);
+    }
+
+    #[test]
+    fn wraps_absolute_angles() {
+        assert_eq!(pack_angle(TargetAngle::Absolute(400)), 0x0028);
+        assert_eq!(pack_angle(TargetAngle::AbsolutePositive(360)), 0x2000);
+        assert_eq!(pack_angle(TargetAngle::AbsoluteNegative(719)), 0x4167);
+    }
+
+    #[test]
+    fn splits_long_multi_targets() {
+        let targets: Vec<TargetCommand> = (0..70)
+            .map(|i| TargetCommand {
+                x_target: i,
+                y_target: i,
+                theta_target: TargetAngle::Unchanged,
+            })
+            .collect();
+        let commands = split_multi_target(Command::MultiTarget {
+            control: 0xff,
+            timeout: 0,
+            move_type: 0,
+            max_speed: 80,
+            speed_change: 0,
+            op_add: WriteMode::Overwrite,
+            targets: targets.clone(),
+        });
+
+        let mut split = vec![];
+        for (i, command) in commands.iter().enumerate() {
+            let Command::MultiTarget {
+                control,
+                op_add,
+                targets,
+                ..
+            } = command
+            else {
+                panic!("expected a multi target command");
+            };
+            assert_eq!(*control, 0xffu8.wrapping_add(i as u8));
+            let mode = if i == 0 {
+                WriteMode::Overwrite
+            } else {
+                WriteMode::Append
+            };
+            assert_eq!(*op_add, mode);
+            assert!(targets.len() <= MAX_TARGETS);
+            split.extend(targets.iter().cloned());
+        }
+        assert_eq!(commands.len(), 3);
+        assert_eq!(split, targets);
+    }
+
+    #[test]
+    fn does_not_split_short_multi_targets() {
+        for (command, _, _, _) in commands() {
+            assert_eq!(split_multi_target(command.clone()), vec![command]);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_commands() {
+        for (command, uuid, bytes, _) in commands() {
+            for len in 0..bytes.len() {
+                let truncated = &bytes[..len];
+
+                // a multi target packet cut between targets is still valid
+                if let Command::MultiTarget { .. } = command {
+                    if len >= 8 && (len - 8) % 6 == 0 {
+                        continue;
+                    }
+                }
+
+                assert_eq!(
+                    decode_command(uuid, truncated),
+                    Err(DecodeError::Truncated {
+                        uuid,
+                        bytes: truncated.to_vec()
+                    }),
+                    "{:?} cut to {} bytes",
+                    command,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_commands_with_trailing_bytes() {
+        for (_, uuid, mut bytes, _) in commands() {
+            // extra bytes are read as a partial target
+            if uuid == MOTOR && bytes[0] == 0x04 {
+                continue;
+            }
+
+            bytes.push(0x00);
+            assert_eq!(
+                decode_command(uuid, &bytes),
+                Err(DecodeError::Unknown {
+                    uuid,
+                    bytes: bytes.clone()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_commands_with_wrong_fixed_bytes() {
+        let packets = vec![
+            (MOTOR, vec![0x01, 0x02, 0x01, 0x64, 0x02, 0x02, 0x14]),
+            (MOTOR, vec![0x01, 0x01, 0x01, 0x64, 0x01, 0x02, 0x14]),
+            (
+                MOTOR,
+                vec![
+                    0x03, 0x00, 0x05, 0x00, 0x50, 0x00, 0x01, 0x2c, 0x01, 0xa0, 0x00, 0x5a, 0x50,
+                ],
+            ),
+            (LIGHT, vec![0x03, 0x10, 0x02, 0x01, 0xff, 0x20, 0x30]),
+            (LIGHT, vec![0x02]),
+            (SOUND, vec![0x04]),
+            (MOTION, vec![0x84]),
+            (BATTERY, vec![0x01]),
+        ];
+
+        for (uuid, bytes) in packets {
+            assert_eq!(
+                decode_command(uuid, &bytes),
+                Err(DecodeError::Unknown {
+                    uuid,
+                    bytes: bytes.clone()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_updates_from_spec_bytes() {
+        for (uuid, bytes, update) in updates() {
+            assert_eq!(decode(uuid, &bytes), Ok(update));
+        }
+    }
+
+    #[test]
+    fn ignores_trailing_update_bytes() {
+        // newer firmware appends fields, such as the sensor angle on position
+        for (uuid, mut bytes, update) in updates() {
+            bytes.push(0x00);
+            assert_eq!(decode(uuid, &bytes), Ok(update));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_updates() {
+        for (uuid, bytes, update) in updates() {
+            for len in 0..bytes.len() {
+                let truncated = &bytes[..len];
+
+                // a motion packet without the shake level is still valid
+                if let Update::Motion { .. } = update {
+                    if len == 5 {
+                        continue;
+                    }
+                }
+
+                assert_eq!(
+                    decode(uuid, truncated),
+                    Err(DecodeError::Truncated {
+                        uuid,
+                        bytes: truncated.to_vec()
+                    }),
+                    "{:?} cut to {} bytes",
+                    update,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_motion_without_shake() {
+        assert_eq!(
+            decode(MOTION, &[0x01, 0x01, 0x01, 0x00, 0x01]),
+            Ok(Update::Motion {
+                horizontal: 1,
+                collision: 1,
+                double_tap: 0,
+                posture: 1,
+                shake: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_updates() {
+        let packets = vec![
+            (POSITION, vec![0x05]),
+            (MOTOR, vec![0x85, 0x00, 0x00]),
+            (MOTION, vec![0x04]),
+            (MOTION, vec![0x03, 0x04]),
+            (BUTTON, vec![0x02, 0x80]),
+            (CONFIG, vec![0x81, 0x00]),
+            (SERVICE, vec![]),
+        ];
+
+        for (uuid, bytes) in packets {
+            let err = decode(uuid, &bytes).unwrap_err();
+            assert_eq!(
+                err,
+                DecodeError::Unknown {
+                    uuid,
+                    bytes: bytes.clone()
+                }
+            );
+            assert_eq!(err.bytes(), &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn formats_decode_errors() {
+        let err = decode(MOTOR, &[0x83]).unwrap_err();
+        assert_eq!(err.to_string(), "Truncated Motor Update: [131]");
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::{Duration, Instant};
+
+use crate::toio::Update;
+
+/// Something that happened to a toio, detected from a change in its updates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CubeEvent {
+    /// The toio was picked up off the mat
+    Lifted,
+    /// The toio was put down on the mat
+    Placed,
+    /// The toio bumped into something
+    Collision,
+    /// The toio was tapped twice
+    DoubleTap,
+    /// The toio was shaken
+    Shake,
+    /// The toio was turned so that a different side faces up, given as the
+    /// posture from the motion sensor
+    Flipped { posture: u8 },
+}
+
+/// Detects events from the updates of a toio, reporting each change once
+/// rather than repeating levels. Going on and off the mat and turning over
+/// only count once they have lasted for the debounce time, and the motion
+/// events only count again once the debounce time has passed since the last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventDetector {
+    debounce: Duration,
+    on_mat: Option<bool>,
+    pending_mat: Option<(bool, Instant)>,
+    posture: Option<u8>,
+    pending_posture: Option<(u8, Instant)>,
+    motion: [(bool, Option<Instant>); 3],
+}
+
+impl CubeEvent {
+    /// Name of the event, such as `lifted` or `doubletap`
+    pub fn name(&self) -> &'static str {
+        return match self {
+            CubeEvent::Lifted => "lifted",
+            CubeEvent::Placed => "placed",
+            CubeEvent::Collision => "collision",
+            CubeEvent::DoubleTap => "doubletap",
+            CubeEvent::Shake => "shake",
+            CubeEvent::Flipped { .. } => "flipped",
+        };
+    }
+}
+
+impl Default for EventDetector {
+    fn default() -> EventDetector {
+        return EventDetector::new(Duration::from_millis(100));
+    }
+}
+
+impl EventDetector {
+    pub fn new(debounce: Duration) -> EventDetector {
+        return EventDetector {
+            debounce,
+            on_mat: None,
+            pending_mat: None,
+            posture: None,
+            pending_posture: None,
+            motion: [(false, None); 3],
+        };
+    }
+
+    /// Records an update from the toio, returning any events that happened
+    pub fn update(&mut self, update: &Update, now: Instant) -> Vec<CubeEvent> {
+        let mut events = vec![];
+
+        match *update {
+            Update::Position { .. } => self.on_mat(true, now),
+            Update::PositionMissed => self.on_mat(false, now),
+            Update::Motion {
+                collision,
+                double_tap,
+                posture,
+                shake,
+                ..
+            } => {
+                let levels = [collision != 0, double_tap != 0, shake != 0];
+                let kinds = [CubeEvent::Collision, CubeEvent::DoubleTap, CubeEvent::Shake];
+                for ((level, kind), (last, fired)) in
+                    levels.into_iter().zip(kinds).zip(self.motion.iter_mut())
+                {
+                    let rested = fired.is_none_or(|at| now.duration_since(at) >= self.debounce);
+                    if level && !*last && rested {
+                        events.push(kind);
+                        *fired = Some(now);
+                    }
+                    *last = level;
+                }
+                self.posture(posture, now);
+            }
+            _ => {}
+        }
+
+        events.extend(self.poll(now));
+        return events;
+    }
+
+    /// Returns the changes that have lasted for the debounce time by now
+    pub fn poll(&mut self, now: Instant) -> Vec<CubeEvent> {
+        let mut events = vec![];
+
+        if let Some((on_mat, since)) = self.pending_mat {
+            if now.duration_since(since) >= self.debounce {
+                self.on_mat = Some(on_mat);
+                self.pending_mat = None;
+                events.push(if on_mat {
+                    CubeEvent::Placed
+                } else {
+                    CubeEvent::Lifted
+                });
+            }
+        }
+
+        if let Some((posture, since)) = self.pending_posture {
+            if now.duration_since(since) >= self.debounce {
+                self.posture = Some(posture);
+                self.pending_posture = None;
+                events.push(CubeEvent::Flipped { posture });
+            }
+        }
+
+        return events;
+    }
+
+    /// When the next pending change will have lasted for the debounce time
+    pub fn deadline(&self) -> Option<Instant> {
+        let mat = self.pending_mat.map(|(_, since)| since + self.debounce);
+        let posture = self.pending_posture.map(|(_, since)| since + self.debounce);
+        return mat.into_iter().chain(posture).min();
+    }
+
+    fn on_mat(&mut self, on_mat: bool, now: Instant) {
+        match self.on_mat {
+            // the first update is where the toio starts, not a change
+            None => self.on_mat = Some(on_mat),
+            Some(current) if current == on_mat => self.pending_mat = None,
+            Some(_) => {
+                if self.pending_mat.map(|(pending, _)| pending) != Some(on_mat) {
+                    self.pending_mat = Some((on_mat, now));
+                }
+            }
+        }
+    }
+
+    fn posture(&mut self, posture: u8, now: Instant) {
+        match self.posture {
+            None => self.posture = Some(posture),
+            Some(current) if current == posture => self.pending_posture = None,
+            Some(_) => {
+                if self.pending_posture.map(|(pending, _)| pending) != Some(posture) {
+                    self.pending_posture = Some((posture, now));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion(collision: u8, posture: u8) -> Update {
+        return Update::Motion {
+            horizontal: 1,
+            collision,
+            double_tap: 0,
+            posture,
+            shake: 0,
+        };
+    }
+
+    #[test]
+    fn reports_each_change_once_after_debouncing() {
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+        let position = Update::Position {
+            x_center: 100,
+            y_center: 100,
+            theta: 0,
+            x_sensor: 100,
+            y_sensor: 100,
+        };
+        let mut detector = EventDetector::new(Duration::from_millis(100));
+
+        assert!(detector.update(&position, ms(0)).is_empty());
+        assert!(detector.update(&motion(0, 1), ms(0)).is_empty());
+
+        // losing the mat for a moment is not a lift
+        assert!(detector.update(&Update::PositionMissed, ms(10)).is_empty());
+        assert!(detector.update(&position, ms(50)).is_empty());
+        assert_eq!(detector.deadline(), None);
+
+        assert!(detector.update(&Update::PositionMissed, ms(200)).is_empty());
+        assert_eq!(detector.deadline(), Some(ms(300)));
+        assert!(detector.poll(ms(250)).is_empty());
+        assert_eq!(detector.poll(ms(300)), vec![CubeEvent::Lifted]);
+        assert!(detector.poll(ms(400)).is_empty());
+
+        // collisions are edges, and repeats within the debounce time are dropped
+        assert_eq!(
+            detector.update(&motion(1, 1), ms(400)),
+            vec![CubeEvent::Collision]
+        );
+        assert!(detector.update(&motion(1, 1), ms(420)).is_empty());
+        assert!(detector.update(&motion(0, 1), ms(440)).is_empty());
+        assert!(detector.update(&motion(1, 1), ms(460)).is_empty());
+        assert!(detector.update(&motion(0, 2), ms(600)).is_empty());
+        assert_eq!(
+            detector.update(&motion(1, 2), ms(700)),
+            vec![CubeEvent::Collision, CubeEvent::Flipped { posture: 2 }]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::motion::{MIN_WHEEL_SPEED, WHEEL_BASE};
+
+/// Distance from the end of a path at which a toio has arrived, in mat units
+const ARRIVE_DISTANCE: f32 = 8.0;
+
+/// Number of points each Bézier curve is sampled into
+const BEZIER_SAMPLES: usize = 16;
+
+/// A path on the mat made of straight segments between points in mat units
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    points: Vec<(f32, f32)>,
+    distances: Vec<f32>,
+}
+
+/// What a toio steered by a controller should do after a position update
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FollowStep {
+    /// Drive each wheel at a signed speed
+    Drive { left: i16, right: i16 },
+    /// The toio has reached the end of the path or the point and should stop
+    Arrived,
+}
+
+/// Steers a toio along a path with pure pursuit, aiming for the point on the
+/// path a fixed distance ahead of the closest point each time its position is
+/// updated
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathFollower {
+    path: Path,
+    speed: i16,
+    lookahead: f32,
+    progress: f32,
+}
+
+impl Path {
+    /// Creates a path through every point in order
+    pub fn polyline(points: Vec<(f32, f32)>) -> Option<Path> {
+        if points.is_empty() {
+            return None;
+        }
+
+        let mut distances = vec![0.0];
+        for segment in points.windows(2) {
+            let last = distances[distances.len() - 1];
+            distances.push(last + distance(segment[0], segment[1]));
+        }
+
+        return Some(Path { points, distances });
+    }
+
+    /// Creates a path from a chain of cubic Bézier curves, given as a start
+    /// point followed by two control points and an end point for each curve
+    pub fn bezier(points: &[(f32, f32)]) -> Option<Path> {
+        if points.len() < 4 || !(points.len() - 1).is_multiple_of(3) {
+            return None;
+        }
+
+        let mut samples = vec![points[0]];
+        for curve in points.windows(4).step_by(3) {
+            for i in 1..=BEZIER_SAMPLES {
+                samples.push(cubic(curve, i as f32 / BEZIER_SAMPLES as f32));
+            }
+        }
+
+        return Path::polyline(samples);
+    }
+
+    /// Total length of the path in mat units
+    pub fn length(&self) -> f32 {
+        return self.distances[self.distances.len() - 1];
+    }
+
+    /// Point at a distance along the path, clamped to its ends
+    pub fn point_at(&self, along: f32) -> (f32, f32) {
+        let along = along.clamp(0.0, self.length());
+        let i = self.distances.partition_point(|d| *d < along);
+        if i == 0 {
+            return self.points[0];
+        }
+
+        let span = self.distances[i] - self.distances[i - 1];
+        let t = if span > 0.0 {
+            (along - self.distances[i - 1]) / span
+        } else {
+            0.0
+        };
+        return lerp(self.points[i - 1], self.points[i], t);
+    }
+
+    /// Distance along the path of the point closest to the given point,
+    /// only considering the part of the path between `from` and `to`
+    fn closest(&self, point: (f32, f32), from: f32, to: f32) -> f32 {
+        let mut closest = (f32::MAX, from);
+
+        for i in 1..self.points.len() {
+            if self.distances[i] < from {
+                continue;
+            }
+            if self.distances[i - 1] > to {
+                break;
+            }
+
+            let (start, end) = (self.points[i - 1], self.points[i]);
+            let span = self.distances[i] - self.distances[i - 1];
+            let t = if span > 0.0 {
+                let along = (point.0 - start.0) * (end.0 - start.0)
+                    + (point.1 - start.1) * (end.1 - start.1);
+                (along / (span * span)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+
+            let along = (self.distances[i - 1] + t * span).clamp(from, to);
+            let gap = distance(point, self.point_at(along));
+            if gap < closest.0 {
+                closest = (gap, along);
+            }
+        }
+
+        return closest.1;
+    }
+}
+
+impl PathFollower {
+    /// Follows a path at a signed wheel speed, where negative speeds are
+    /// treated as positive, aiming `lookahead` mat units ahead on the path
+    pub fn new(path: Path, speed: i16, lookahead: f32) -> PathFollower {
+        return PathFollower {
+            path,
+            speed: speed.saturating_abs(),
+            lookahead: lookahead.max(1.0),
+            progress: 0.0,
+        };
+    }
+
+    /// Fraction of the path that has been followed, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        let length = self.path.length();
+        if length == 0.0 {
+            return 1.0;
+        }
+        return self.progress / length;
+    }
+
+    /// Works out how to drive towards the path from the position of the toio
+    /// in mat units and its angle in degrees
+    pub fn update(&mut self, x: f32, y: f32, theta: f32) -> FollowStep {
+        let position = (x, y);
+        let length = self.path.length();
+
+        // only look a little way ahead so that crossings are followed in order
+        self.progress = self
+            .path
+            .closest(position, self.progress, self.progress + self.lookahead);
+
+        let end = self.path.point_at(length);
+        let remaining = distance(position, end);
+        if self.progress >= length - self.lookahead && remaining < ARRIVE_DISTANCE {
+            self.progress = length;
+            return FollowStep::Arrived;
+        }
+
+        let target = self.path.point_at(self.progress + self.lookahead);
+        let (dx, dy) = (target.0 - x, target.1 - y);
+        let gap = dx.hypot(dy).max(1.0);
+
+        // angle to the target relative to the heading of the toio, which is
+        // clockwise on the mat like the angle of the position
+        let mut alpha = dy.atan2(dx) - theta.to_radians();
+        while alpha > PI {
+            alpha -= 2.0 * PI;
+        }
+        while alpha < -PI {
+            alpha += 2.0 * PI;
+        }
+
+        // slow down on the way into the end of the path
+        let mut speed = self.speed as f32;
+        if self.progress >= length - self.lookahead {
+            speed = (speed * remaining / self.lookahead).max(MIN_WHEEL_SPEED as f32);
+        }
+
+        // turn on the spot towards targets behind the toio
+        if alpha.abs() > FRAC_PI_2 {
+            let turn = speed.copysign(alpha) as i16;
+            return FollowStep::Drive {
+                left: turn,
+                right: -turn,
+            };
+        }
+
+        let curvature = 2.0 * alpha.sin() / gap;
+        let left = speed * (1.0 + curvature * WHEEL_BASE / 2.0);
+        let right = speed * (1.0 - curvature * WHEEL_BASE / 2.0);
+
+        return FollowStep::Drive {
+            left: left.round() as i16,
+            right: right.round() as i16,
+        };
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    return (b.0 - a.0).hypot(b.1 - a.1);
+}
+
+fn lerp(a: (f32, f32), b: (f32, f32), t: f32) -> (f32, f32) {
+    return (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+}
+
+/// Point on a cubic Bézier curve
+fn cubic(curve: &[(f32, f32)], t: f32) -> (f32, f32) {
+    let u = 1.0 - t;
+    let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+    return curve
+        .iter()
+        .zip(weights)
+        .fold((0.0, 0.0), |sum, (point, weight)| {
+            (sum.0 + point.0 * weight, sum.1 + point.1 * weight)
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Follows a path by moving the toio to each point along it in turn,
+    /// facing the way the path goes, and returns the progress at each point
+    /// along with the last step
+    fn walk(follower: &mut PathFollower, path: &Path) -> (Vec<f32>, FollowStep) {
+        let mut progress = vec![];
+        let mut step = FollowStep::Arrived;
+        let mut along = 0.0;
+        while along <= path.length() {
+            let (x, y) = path.point_at(along);
+            let (ahead_x, ahead_y) = path.point_at(along + 1.0);
+            let theta = (ahead_y - y).atan2(ahead_x - x).to_degrees();
+            step = follower.update(x, y, theta);
+            progress.push(follower.progress());
+            along += 5.0;
+        }
+        return (progress, step);
+    }
+
+    #[test]
+    fn follows_a_polyline_to_the_end() {
+        let path = Path::polyline(vec![(0.0, 0.0), (100.0, 0.0), (100.0, 100.0)]).unwrap();
+        assert_eq!(path.length(), 200.0);
+        assert_eq!(path.point_at(150.0), (100.0, 50.0));
+        assert_eq!(Path::polyline(vec![]), None);
+
+        // drives straight at a point ahead, and turns right towards a point
+        // further round the clockwise angle
+        let mut follower = PathFollower::new(path.clone(), 50, 30.0);
+        assert_eq!(
+            follower.update(0.0, 0.0, 0.0),
+            FollowStep::Drive {
+                left: 50,
+                right: 50
+            }
+        );
+        match follower.update(80.0, 0.0, 0.0) {
+            FollowStep::Drive { left, right } => assert!(left > right),
+            step => panic!("expected to drive, got {:?}", step),
+        }
+
+        let mut follower = PathFollower::new(path.clone(), 50, 30.0);
+        let (progress, step) = walk(&mut follower, &path);
+        assert!(progress.windows(2).all(|p| p[1] >= p[0]));
+        assert!((progress[20] - 0.5).abs() < 0.01);
+        assert_eq!(step, FollowStep::Arrived);
+        assert_eq!(follower.progress(), 1.0);
+    }
+
+    #[test]
+    fn follows_a_bezier_curve_to_the_end() {
+        let curve = [(0.0, 0.0), (0.0, 100.0), (100.0, 100.0), (100.0, 0.0)];
+        let path = Path::bezier(&curve).unwrap();
+        assert_eq!(path.point_at(0.0), (0.0, 0.0));
+        assert_eq!(path.point_at(path.length()), (100.0, 0.0));
+        assert_eq!(path.points[BEZIER_SAMPLES / 2], (50.0, 75.0));
+        assert_eq!(Path::bezier(&curve[..3]), None);
+
+        let mut follower = PathFollower::new(path.clone(), 50, 20.0);
+        let (progress, step) = walk(&mut follower, &path);
+        assert!(progress.windows(2).all(|p| p[1] >= p[0]));
+        let middle = progress[progress.len() / 2];
+        assert!((middle - 0.5).abs() < 0.05, "{}", middle);
+        assert_eq!(step, FollowStep::Arrived);
+        assert_eq!(follower.progress(), 1.0);
+    }
+}
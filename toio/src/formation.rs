@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Targets sent to toios to form a shape, waiting for each toio to respond
+#[derive(Clone, Debug, PartialEq)]
+pub struct Formation {
+    waiting: HashMap<usize, u8>,
+    assigned: usize,
+    arrived: usize,
+    started: Instant,
+}
+
+impl Formation {
+    pub fn new() -> Formation {
+        return Formation {
+            waiting: HashMap::new(),
+            assigned: 0,
+            arrived: 0,
+            started: Instant::now(),
+        };
+    }
+
+    /// Time the formation was started, which tells formations apart
+    pub fn started(&self) -> Instant {
+        return self.started;
+    }
+
+    /// Waits for the toio with the given ID to respond to the target command
+    /// with the given control ID
+    pub fn add(&mut self, id: usize, control: u8) {
+        self.waiting.insert(id, control);
+        self.assigned += 1;
+    }
+
+    /// Records the response of a toio to a target command. Once every toio has
+    /// responded, returns how many arrived and how many were assigned a goal.
+    pub fn respond(&mut self, id: usize, control: u8, response: u8) -> Option<(usize, usize)> {
+        if self.waiting.get(&id) != Some(&control) {
+            return None;
+        }
+        self.waiting.remove(&id);
+        if response == 0 {
+            self.arrived += 1;
+        }
+
+        if !self.waiting.is_empty() {
+            return None;
+        }
+        return Some((self.arrived, self.assigned));
+    }
+
+    /// Records that the toio with the given ID will not reach its goal, such
+    /// as when it is lifted or disconnects. Once every toio has responded,
+    /// returns how many arrived and how many were assigned a goal.
+    pub fn leave(&mut self, id: usize) -> Option<(usize, usize)> {
+        let control = *self.waiting.get(&id)?;
+        return self.respond(id, control, 1);
+    }
+
+    /// Stops waiting for the toios that have not responded, returning how many
+    /// arrived and how many were assigned a goal
+    pub fn abandon(&mut self) -> (usize, usize) {
+        self.waiting.clear();
+        return (self.arrived, self.assigned);
+    }
+}
+
+impl Default for Formation {
+    fn default() -> Formation {
+        return Formation::new();
+    }
+}
+
+/// Assigns goals to toios so that the total distance they travel is as short as
+/// possible, using the Hungarian algorithm. Returns the index of the goal for
+/// each position, where toios are left without a goal when there are more of
+/// them than goals.
+pub fn assign(positions: &[(f32, f32)], goals: &[(f32, f32)]) -> Vec<Option<usize>> {
+    // pad the costs into a square, where missing toios or goals cost nothing
+    let n = positions.len().max(goals.len());
+    let cost = |row: usize, col: usize| -> f64 {
+        return match (positions.get(row), goals.get(col)) {
+            (Some(position), Some(goal)) => {
+                ((goal.0 - position.0) as f64).hypot((goal.1 - position.1) as f64)
+            }
+            _ => 0.0,
+        };
+    };
+
+    // potentials for rows and columns, and the row matched to each column,
+    // where index 0 is a placeholder for the row being matched
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut matched = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+
+    for row in 1..=n {
+        matched[0] = row;
+        let mut col0 = 0;
+        let mut min = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+
+        // grow a path of tight edges until it reaches an unmatched column
+        loop {
+            used[col0] = true;
+            let row0 = matched[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+
+            for col in 1..=n {
+                if used[col] {
+                    continue;
+                }
+                let reduced = cost(row0 - 1, col - 1) - u[row0] - v[col];
+                if reduced < min[col] {
+                    min[col] = reduced;
+                    way[col] = col0;
+                }
+                if min[col] < delta {
+                    delta = min[col];
+                    col1 = col;
+                }
+            }
+
+            for col in 0..=n {
+                if used[col] {
+                    u[matched[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min[col] -= delta;
+                }
+            }
+
+            col0 = col1;
+            if matched[col0] == 0 {
+                break;
+            }
+        }
+
+        // flip the matches along the path
+        loop {
+            let col1 = way[col0];
+            matched[col0] = matched[col1];
+            col0 = col1;
+            if col0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; positions.len()];
+    for (goal, row) in matched.iter().skip(1).enumerate() {
+        let row = row - 1;
+        if row < positions.len() && goal < goals.len() {
+            assignment[row] = Some(goal);
+        }
+    }
+    return assignment;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(positions: &[(f32, f32)], goals: &[(f32, f32)], assignment: &[usize]) -> f32 {
+        return positions
+            .iter()
+            .zip(assignment)
+            .map(|(p, g)| (goals[*g].0 - p.0).hypot(goals[*g].1 - p.1))
+            .sum();
+    }
+
+    fn permutations(n: usize) -> Vec<Vec<usize>> {
+        if n == 0 {
+            return vec![vec![]];
+        }
+        let mut all = vec![];
+        for rest in permutations(n - 1) {
+            for i in 0..=rest.len() {
+                let mut permutation = rest.clone();
+                permutation.insert(i, n - 1);
+                all.push(permutation);
+            }
+        }
+        return all;
+    }
+
+    #[test]
+    fn assigns_shortest_total_distance() {
+        let positions = [
+            (50.0, 50.0),
+            (300.0, 60.0),
+            (120.0, 400.0),
+            (400.0, 380.0),
+            (250.0, 250.0),
+        ];
+        let goals = [
+            (260.0, 240.0),
+            (100.0, 100.0),
+            (380.0, 100.0),
+            (200.0, 390.0),
+            (90.0, 300.0),
+        ];
+
+        let assignment: Vec<usize> = assign(&positions, &goals)
+            .into_iter()
+            .map(|goal| goal.unwrap())
+            .collect();
+        let best = permutations(goals.len())
+            .iter()
+            .map(|permutation| total(&positions, &goals, permutation))
+            .fold(f32::INFINITY, f32::min);
+
+        assert!((total(&positions, &goals, &assignment) - best).abs() < 1e-3);
+    }
+
+    #[test]
+    fn leaves_extra_toios_without_goals() {
+        let positions = [(0.0, 0.0), (100.0, 0.0), (200.0, 0.0)];
+        let goals = [(210.0, 0.0), (0.0, 10.0)];
+        assert_eq!(assign(&positions, &goals), vec![Some(1), None, Some(0)]);
+    }
+
+    #[test]
+    fn leaves_extra_goals_unassigned() {
+        let positions = [(100.0, 0.0)];
+        let goals = [(0.0, 0.0), (110.0, 0.0), (300.0, 0.0)];
+        assert_eq!(assign(&positions, &goals), vec![Some(1)]);
+    }
+
+    #[test]
+    fn completes_once_every_toio_responds() {
+        let mut formation = Formation::new();
+        formation.add(0, 10);
+        formation.add(3, 11);
+
+        assert_eq!(formation.respond(0, 9, 0), None);
+        assert_eq!(formation.respond(0, 10, 0), None);
+        assert_eq!(formation.respond(3, 11, 2), Some((1, 2)));
+    }
+
+    #[test]
+    fn completes_when_toios_leave_or_it_is_abandoned() {
+        let mut formation = Formation::new();
+        formation.add(0, 10);
+        formation.add(1, 11);
+        formation.add(2, 12);
+
+        assert_eq!(formation.leave(5), None);
+        assert_eq!(formation.respond(0, 10, 0), None);
+        assert_eq!(formation.leave(0), None);
+        assert_eq!(formation.leave(1), None);
+        assert_eq!(formation.abandon(), (1, 3));
+
+        let mut formation = Formation::new();
+        formation.add(4, 20);
+        assert_eq!(formation.leave(4), Some((0, 1)));
+    }
+}
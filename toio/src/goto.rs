@@ -0,0 +1,202 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::time::{Duration, Instant};
+
+use btleplug::api::Peripheral;
+use futures::stream::StreamExt;
+use tokio::time::timeout;
+
+use crate::codec::*;
+use crate::follow::FollowStep;
+use crate::motion::{MoveError, MAX_WHEEL_SPEED, MIN_WHEEL_SPEED};
+use crate::toio::*;
+
+/// Options for steering a toio to a point from the host
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GoToOptions {
+    /// Fastest wheel speed to drive at
+    pub max_speed: i16,
+    /// Wheel speed per mat unit of distance from the point
+    pub distance_gain: f32,
+    /// Difference in wheel speed per radian of angle away from the point
+    pub angle_gain: f32,
+    /// Distance from the point at which the toio has arrived, in mat units
+    pub tolerance: f32,
+    /// How long the toio can be off the mat before giving up
+    pub missed_timeout: Duration,
+}
+
+/// Steers a toio to a point with a proportional controller each time its
+/// position is updated. Unlike a target command sent to the firmware, the toio
+/// keeps going when it loses the mat for less than the missed timeout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoTo {
+    x: f32,
+    y: f32,
+    options: GoToOptions,
+    seen: Instant,
+}
+
+impl Default for GoToOptions {
+    fn default() -> GoToOptions {
+        return GoToOptions {
+            max_speed: 80,
+            distance_gain: 1.0,
+            angle_gain: 50.0,
+            tolerance: 8.0,
+            missed_timeout: Duration::from_millis(500),
+        };
+    }
+}
+
+impl GoTo {
+    /// Goes to a point in mat units
+    pub fn new(x: f32, y: f32, options: GoToOptions) -> GoTo {
+        return GoTo {
+            x,
+            y,
+            options,
+            seen: Instant::now(),
+        };
+    }
+
+    /// Works out how to drive towards the point from the position of the toio
+    /// in mat units and its angle in degrees
+    pub fn update(&mut self, x: f32, y: f32, theta: f32) -> FollowStep {
+        self.seen = Instant::now();
+
+        let (dx, dy) = (self.x - x, self.y - y);
+        let distance = dx.hypot(dy);
+        if distance < self.options.tolerance {
+            return FollowStep::Arrived;
+        }
+
+        // angle to the point relative to the heading of the toio, driving
+        // backwards to points behind it
+        let mut alpha = dy.atan2(dx) - theta.to_radians();
+        while alpha > PI {
+            alpha -= 2.0 * PI;
+        }
+        while alpha < -PI {
+            alpha += 2.0 * PI;
+        }
+        let direction = if alpha.abs() > FRAC_PI_2 {
+            alpha -= PI.copysign(alpha);
+            -1.0
+        } else {
+            1.0
+        };
+
+        let max_speed = self
+            .options
+            .max_speed
+            .clamp(MIN_WHEEL_SPEED as i16, MAX_WHEEL_SPEED as i16) as f32;
+        let forward = (self.options.distance_gain * distance)
+            .clamp(MIN_WHEEL_SPEED as f32, max_speed)
+            * alpha.cos()
+            * direction;
+        let turn = self.options.angle_gain * alpha;
+
+        // keep the difference between the wheels when either is too fast
+        let (left, right) = (forward + turn, forward - turn);
+        let scale = (max_speed / left.abs().max(right.abs())).min(1.0);
+
+        return FollowStep::Drive {
+            left: (left * scale).round() as i16,
+            right: (right * scale).round() as i16,
+        };
+    }
+
+    /// Options the toio is steered with
+    pub fn options(&self) -> GoToOptions {
+        return self.options;
+    }
+
+    /// Whether the position of the toio has been missing for longer than the
+    /// missed timeout
+    pub fn lost(&self) -> bool {
+        return self.seen.elapsed() > self.options.missed_timeout;
+    }
+}
+
+impl ToioPeripheral {
+    /// Steers to a point on the mat from the host, resolving once the toio has
+    /// arrived or has been off the mat for longer than the missed timeout
+    pub async fn go_to(&self, x: u16, y: u16, options: GoToOptions) -> Result<(), MoveError> {
+        let mut controller = GoTo::new(x as f32, y as f32, options);
+        let mut notifications = self.peripheral.notifications().await?;
+
+        loop {
+            let notification = match timeout(options.missed_timeout, notifications.next()).await {
+                Ok(Some(notification)) => Some(notification),
+                Ok(None) => return Err(MoveError::NoResponse),
+                Err(_) => None,
+            };
+
+            let update = notification.map(|n| decode(n.uuid, &n.value));
+            if let Some(Ok(Update::Position {
+                x_center,
+                y_center,
+                theta,
+                ..
+            })) = update
+            {
+                match controller.update(x_center as f32, y_center as f32, theta as f32) {
+                    FollowStep::Drive { left, right } => self.drive(left, right).await?,
+                    FollowStep::Arrived => {
+                        self.stop().await?;
+                        return Ok(());
+                    }
+                }
+            } else if controller.lost() {
+                self.stop().await?;
+                return Err(MoveError::PositionMissed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wheels(step: FollowStep) -> (i16, i16) {
+        return match step {
+            FollowStep::Drive { left, right } => (left, right),
+            FollowStep::Arrived => panic!("arrived too soon"),
+        };
+    }
+
+    #[test]
+    fn drives_towards_the_point() {
+        let mut goto = GoTo::new(300.0, 200.0, GoToOptions::default());
+
+        // straight ahead, and straight behind by reversing
+        assert_eq!(wheels(goto.update(100.0, 200.0, 0.0)), (80, 80));
+        assert_eq!(wheels(goto.update(400.0, 200.0, 0.0)), (-80, -80));
+
+        // slows down close to the point
+        assert_eq!(wheels(goto.update(280.0, 200.0, 0.0)), (20, 20));
+
+        // turns right towards points clockwise from the heading, and left
+        // towards points anticlockwise from it, within the fastest speed
+        let (left, right) = wheels(goto.update(200.0, 100.0, 0.0));
+        assert!(left > right && left <= 80);
+        let (left, right) = wheels(goto.update(200.0, 300.0, 0.0));
+        assert!(right > left && right <= 80);
+
+        // and turns on the spot towards points off to the side
+        let (left, right) = wheels(goto.update(300.0, 100.0, 0.0));
+        assert!(left > 0 && right < 0);
+    }
+
+    #[test]
+    fn arrives_within_the_tolerance() {
+        let options = GoToOptions {
+            tolerance: 10.0,
+            ..GoToOptions::default()
+        };
+        let mut goto = GoTo::new(300.0, 200.0, options);
+        assert_ne!(goto.update(311.0, 200.0, 90.0), FollowStep::Arrived);
+        assert_eq!(goto.update(306.0, 206.0, 90.0), FollowStep::Arrived);
+    }
+}
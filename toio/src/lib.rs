@@ -0,0 +1,48 @@
+#![allow(clippy::needless_return)]
+
+//! Library for connecting to toio cubes over Bluetooth LE.
+//!
+//! Use a [`ToioScanner`] to search for toios, which yields a [`ToioPeripheral`]
+//! for each toio that connects. Send it a [`Command`] with
+//! [`ToioPeripheral::send_command`] and listen for each [`Update`] from
+//! [`ToioPeripheral::updates`], or use methods such as
+//! [`ToioPeripheral::move_to`] and [`ToioPeripheral::drive`] to move it. The
+//! [`codec`] module converts commands and updates to and from the bytes of each
+//! characteristic without Bluetooth.
+//!
+//! The OSC bridge and terminal UI are built on top of this library as the
+//! `toio` binary, behind the `osc`, `tui` and `websocket` features.
+
+mod avoid;
+mod button;
+pub mod codec;
+mod events;
+mod follow;
+mod formation;
+mod goto;
+mod mat;
+mod motion;
+mod odometry;
+mod pose;
+mod standard;
+mod state;
+mod targets;
+mod toio;
+
+pub use crate::avoid::*;
+pub use crate::button::*;
+pub use crate::codec::{
+    uuid_to_string, BATTERY, BUTTON, CONFIG, LIGHT, MOTION, MOTOR, POSITION, SERVICE, SOUND,
+};
+pub use crate::events::*;
+pub use crate::follow::*;
+pub use crate::formation::*;
+pub use crate::goto::*;
+pub use crate::mat::*;
+pub use crate::motion::*;
+pub use crate::odometry::*;
+pub use crate::pose::*;
+pub use crate::standard::*;
+pub use crate::state::*;
+pub use crate::targets::*;
+pub use crate::toio::*;
@@ -1,22 +1,35 @@
+#![allow(clippy::needless_return)]
+
 mod osc;
-mod toio;
+mod record;
+mod server;
+mod slip;
 mod ui;
+#[cfg(feature = "websocket")]
+mod ws;
 
-use osc::*;
+use record::*;
+use server::*;
 use toio::*;
 use ui::*;
+#[cfg(feature = "websocket")]
+use ws::*;
 
+use std::collections::HashMap;
 use std::error::Error;
-use std::net::UdpSocket;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::process;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use std::vec;
 
 use clap::Parser;
 use futures::future::join_all;
 use futures::future::Either::{Left, Right};
 use tokio::sync::RwLock;
+use tokio::time::{interval, sleep, sleep_until, Interval};
 
 #[derive(Parser)]
 #[command(name = "toio")]
@@ -29,6 +42,88 @@ struct Args {
     #[arg(short, long)]
     remote: Option<usize>,
 
+    /// Also accept OSC over TCP with SLIP framing on this port
+    #[arg(long)]
+    tcp: Option<u16>,
+
+    /// Also accept JSON commands over WebSocket on this port
+    #[cfg(feature = "websocket")]
+    #[arg(short, long)]
+    websocket: Option<u16>,
+
+    /// Steer toios driven from the host around each other, which does not
+    /// apply to targets driven by the toios themselves such as /formation
+    #[arg(long)]
+    avoid: bool,
+
+    /// Radius of each cube in mat units when avoiding collisions
+    #[arg(long, default_value_t = 20.0)]
+    radius: f32,
+
+    /// Mats the toios are on, laid out in rows (comma-separated list e.g. simple,simple).
+    /// A toio is followed between mats with the same coordinates as it drives
+    /// across them
+    #[arg(long, value_delimiter = ',')]
+    mats: Option<Vec<MatType>>,
+
+    /// Number of mats in each row of the layout
+    #[arg(long, default_value_t = 1)]
+    columns: usize,
+
+    /// Place mats in a shared world frame from a layout file, with a line for
+    /// each mat such as `simple 0 0 90`, instead of a grid
+    #[arg(long)]
+    layout: Option<PathBuf>,
+
+    /// Units of positions sent and received over OSC (raw, mm or normalized),
+    /// which are mm by default with a layout file and raw otherwise
+    #[arg(long)]
+    units: Option<Units>,
+
+    /// Name cards from a mapping file, with a line for each card such as `3670337 start`
+    #[arg(long)]
+    cards: Option<PathBuf>,
+
+    /// Send the estimated pose of each cube over /pose this many times a second
+    #[arg(long)]
+    pose: Option<f32>,
+
+    /// Turn on motor speed notifications as each cube connects, to track odometry
+    #[arg(long)]
+    motor_speed: bool,
+
+    /// Milliseconds a change must last before it is sent as an /event
+    #[arg(long, default_value_t = 100)]
+    debounce: u64,
+
+    /// Milliseconds the button is held for a /button/longpress
+    #[arg(long, default_value_t = 500)]
+    long_press: u64,
+
+    /// Milliseconds within which a second click is a /button/doubleclick
+    #[arg(long, default_value_t = 300)]
+    double_click: u64,
+
+    /// Record every OSC packet received and sent to a file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Replay the commands in a recording once the cubes it uses connect
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Speed to replay at, where 2 is twice as fast
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f32,
+
+    /// Replay the commands for one cube to another (comma-separated list e.g. 0:2,1:3)
+    #[arg(long, value_delimiter = ',', value_parser = parse_remap)]
+    remap: Option<Vec<(usize, usize)>>,
+
+    /// Set addresses to listen on (comma-separated list e.g. 0.0.0.0,::)
+    #[arg(short, long, value_delimiter = ',')]
+    bind: Option<Vec<IpAddr>>,
+
     /// Show terminal UI
     #[arg(short, long)]
     terminal: bool,
@@ -76,56 +171,109 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
     // let scanner = ToioScanner::new_with_filter(true, vec![3, 100]).await?;
     let mut toios = scanner.search().await?;
-    let connected: Arc<RwLock<Vec<Arc<RwLock<Toio>>>>> = Arc::new(RwLock::new(vec![]));
+    let connected: Connected = Arc::new(RwLock::new(vec![]));
+    let swarm = Arc::new(RwLock::new(Swarm::new(args.avoid, args.radius)));
 
     // server and client address
-    let host_addr = format!("0.0.0.0:{}", args.port.unwrap_or(3334));
-    let to_addr = format!("0.0.0.0:{}", args.remote.unwrap_or(3333));
+    let port = args.port.unwrap_or(3334) as u16;
+    let host_addrs: Vec<SocketAddr> = args
+        .bind
+        .clone()
+        .unwrap_or(vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)])
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    let to_addr = SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        args.remote.unwrap_or(3333) as u16,
+    );
     if args.terminal {
-        println!(
-            "Listening on port {} and sending to port {}",
-            host_addr, to_addr
-        )
+        println!("Listening on {:?} and sending to {}", host_addrs, to_addr)
     }
 
-    // open socket and create buffer
-    let socket = Arc::new(UdpSocket::bind(&host_addr)?);
-    let mut buf = [0u8; rosc::decoder::MTU];
+    // open sockets and whenever a message is recieved through OSC, forward to toio
+    let (layout, units) = match (&args.layout, &args.mats) {
+        (Some(path), _) => (
+            fs::read_to_string(path)?.parse::<MatLayout>()?,
+            args.units.unwrap_or(Units::Millimetres),
+        ),
+        (None, Some(mats)) => (
+            MatLayout::grid(mats, args.columns)?,
+            args.units.unwrap_or_default(),
+        ),
+        (None, None) => (MatLayout::default(), args.units.unwrap_or_default()),
+    };
+    let cards = Arc::new(match &args.cards {
+        Some(path) => fs::read_to_string(path)?.parse::<StandardNames>()?,
+        None => StandardNames::new(),
+    });
+    let mut server = OscServer::bind(&host_addrs, args.tcp, to_addr)?.with_mats(layout, units);
+    if let Some(path) = &args.record {
+        server = server.with_recorder(Recorder::create(path)?);
+    }
+    #[cfg_attr(not(feature = "websocket"), allow(unused_mut))]
+    let mut listeners = server.listen(connected.clone(), swarm.clone());
+
+    // replay a recording alongside any commands that are received
+    if let Some(path) = &args.replay {
+        let records = read_records(path)?;
+        let remap: HashMap<usize, usize> =
+            args.remap.clone().unwrap_or_default().into_iter().collect();
+        let (server, connected, swarm) = (server.clone(), connected.clone(), swarm.clone());
+        let speed = args.replay_speed;
+        tokio::spawn(async move {
+            server
+                .replay(connected, swarm, records, speed, &remap)
+                .await;
+        });
+    }
 
-    // whenever a message is recieved through OSC, forward to toio
-    let sock = socket.clone();
-    let connected_clone = connected.clone();
-    tokio::spawn(async move {
-        // let mut now = SystemTime::now();
-        while let Ok(size) = sock.recv(&mut buf) {
-            if let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..size]) {
-                if let Some((toionum, cmd)) = handle_packet(packet) {
-                    let connected_read = connected_clone.read().await;
-                    if toionum < connected_read.len() {
-                        let toio = connected_read[toionum].read().await;
-
-                        let last_command = toio.get_last_command();
-                        let mut last_command_write = last_command.write().await;
-                        *last_command_write = Some(SystemTime::now());
-
-                        toio.toio.send_command(cmd).await;
-                    }
-                }
-            }
+    // open WebSocket listeners on the same addresses
+    #[cfg(feature = "websocket")]
+    let websocket = match args.websocket {
+        Some(port) => {
+            let ws_addrs: Vec<SocketAddr> = host_addrs
+                .iter()
+                .map(|addr| SocketAddr::new(addr.ip(), port))
+                .collect();
+            let websocket = WsServer::bind(&ws_addrs)?;
+            listeners.append(&mut websocket.listen(connected.clone()));
+            Some(websocket)
         }
-    });
+        None => None,
+    };
 
     // whenever we connect to a toio, add it to the list
     let connected_clone = connected.clone();
+    let server_clone = server.clone();
+    #[cfg(feature = "websocket")]
+    let websocket_clone = websocket.clone();
     tokio::spawn(async move {
         while let Some(peripheral_update) = toios.next().await {
             match peripheral_update {
                 Left(toio_peripheral) => {
-                    // clone socket
-                    let sock = socket.clone();
+                    // clone server
+                    let server = server_clone.clone();
+                    let connected = connected_clone.clone();
+                    let swarm = swarm.clone();
+                    let cards = cards.clone();
+                    let pose_rate = args.pose;
+                    let debounce = Duration::from_millis(args.debounce);
+                    let timings = ButtonTimings {
+                        long_press: Duration::from_millis(args.long_press),
+                        double_click: Duration::from_millis(args.double_click),
+                    };
+                    #[cfg(feature = "websocket")]
+                    let websocket = websocket_clone.clone();
 
                     // listen for updates from toio
                     let mut updates = toio_peripheral.updates().await.unwrap();
+                    if args.motor_speed {
+                        let enable = Command::MotorSpeedConfig { enabled: true };
+                        if let Err(err) = toio_peripheral.send_command(enable).await {
+                            eprintln!("Error turning on motor speed notifications: {}", err);
+                        }
+                    }
 
                     // create instance of Toio to record toio info
                     let mut toio = Toio::new(toio_peripheral);
@@ -133,31 +281,325 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         println!("Toio Connected: {}", toio.id);
                     }
 
-                    let battery = toio.get_battery();
                     let last_update = toio.get_last_update();
+                    let pending_targets = toio.get_pending_targets();
+                    let queued_targets = toio.get_queued_targets();
+                    let controller = toio.get_controller();
+                    let decode_errors = toio.get_decode_errors();
+                    let odometry = toio.get_odometry();
+                    let state = toio.get_state();
 
                     // request permission to write to list of connected toios
                     let mut connected_write = connected_clone.write().await;
                     let id = connected_write.len();
 
                     // start process to listen for messages from toio
-                    let toio_channel = tokio::spawn({
-                        let to_addr = to_addr.clone();
-                        async move {
-                            while let Some(update) = updates.next().await {
-                                // if it is a battery update, record it in the Toio
-                                if let Update::Battery { level } = update {
-                                    let mut battery = battery.write().await;
-                                    *battery = Some(level);
+                    let toio_channel = tokio::spawn(async move {
+                        let mut path_percent = None;
+                        let mut card = None;
+                        let mut pose = PoseEstimator::default();
+                        let mut events = EventDetector::new(debounce);
+                        let mut button = ButtonGestures::new(timings);
+                        let mut pose_interval = pose_rate
+                            .map(|rate| interval(Duration::from_secs_f32(1.0 / rate.max(0.1))));
+
+                        loop {
+                            // publish the estimated pose at a fixed rate between updates, and
+                            // report changes and long presses once they have lasted long enough
+                            let result = tokio::select! {
+                                result = updates.next_result() => match result {
+                                    Some(result) => result,
+                                    None => break,
+                                },
+                                now = tick(&mut pose_interval) => {
+                                    if let Some(estimate) = pose.predict(now.into_std()) {
+                                        let world = state.read().await.world;
+                                        server.report(server.send_pose(id, estimate, world).await);
+                                    }
+                                    continue;
+                                }
+                                _ = wait_until(events.deadline()) => {
+                                    for event in events.poll(Instant::now()) {
+                                        server.report(server.send_event(id, event).await);
+                                    }
+                                    continue;
+                                }
+                                _ = wait_until(button.deadline()) => {
+                                    for event in button.poll(Instant::now()) {
+                                        server.report(server.send_button(id, event).await);
+                                    }
+                                    continue;
+                                }
+                            };
+
+                            // if the notification failed to decode, count it and skip it
+                            let update = match result {
+                                Ok(update) => update,
+                                Err(_) => {
+                                    *decode_errors.write().await = updates.errors();
+                                    continue;
+                                }
+                            };
+
+                            // record the update, such as the battery level, in the Toio, and
+                            // place the toio in the world frame, following it from mat to mat
+                            let world = {
+                                let mut state = state.write().await;
+                                state.apply(&update);
+                                if let Update::Position {
+                                    x_center,
+                                    y_center,
+                                    theta,
+                                    ..
+                                } = update
+                                {
+                                    state.world = server
+                                        .locate(x_center, y_center, theta, state.world.as_ref())
+                                        .await;
                                 }
+                                state.world
+                            };
+
+                            if let Update::MotorTargetResponse { control, response }
+                            | Update::MultiTargetResponse { control, response } = update
+                            {
+                                // send the next part of a long multi target command, or drop the
+                                // rest of it if this part did not finish
+                                let next = queued_targets.write().await.respond(control, response);
+                                let failed = match next {
+                                    Some(next) => send_part(&connected, id, next).await,
+                                    None => None,
+                                };
+
+                                // if the toio is part of a formation, report the formation once
+                                // every toio has responded
+                                server.respond_formation(id, control, response).await;
+
+                                // if it is a response to an acknowledged target command, send
+                                // the ack once every part has finished or any part has failed
+                                let ack = {
+                                    let mut pending = pending_targets.write().await;
+                                    match pending.remove(&control) {
+                                        Some((seq, origin)) if response != 0 => {
+                                            pending.retain(|_, p| *p != (seq, origin));
+                                            Some((seq, origin, response as i32))
+                                        }
+                                        Some((seq, origin))
+                                            if !pending.values().any(|p| *p == (seq, origin)) =>
+                                        {
+                                            Some((seq, origin, response as i32))
+                                        }
+                                        _ => None,
+                                    }
+                                };
+
+                                // send the ack back to wherever the command came from
+                                for (seq, origin, status) in ack.into_iter().chain(failed) {
+                                    match origin {
+                                        Origin::Osc => {
+                                            server.report(server.send_ack(id, seq, status).await)
+                                        }
+                                        Origin::WebSocket =>
+                                        {
+                                            #[cfg(feature = "websocket")]
+                                            if let Some(websocket) = &websocket {
+                                                websocket.send_ack(id, seq, status);
+                                            }
+                                        }
+                                    }
+                                }
+                            }
 
-                                // record time of update
-                                let mut last_update = last_update.write().await;
-                                *last_update = Some(SystemTime::now());
+                            // correct the estimated pose
+                            let now = Instant::now();
+                            match update {
+                                Update::Position {
+                                    x_center,
+                                    y_center,
+                                    theta,
+                                    ..
+                                } => pose.position(
+                                    x_center as f32,
+                                    y_center as f32,
+                                    theta as f32,
+                                    now,
+                                ),
+                                Update::PositionMissed => pose.missed(now),
+                                Update::MotorSpeed {
+                                    left_speed,
+                                    right_speed,
+                                } => pose.motor_speed(left_speed, right_speed, now),
+                                Update::PostureEuler { yaw, .. } => pose.posture(yaw as f32, now),
+                                _ => {}
+                            }
 
-                                send_packet(&sock, &to_addr, id, update);
+                            // report each change, such as the toio being lifted, once
+                            for event in events.update(&update, now) {
+                                server.report(server.send_event(id, event).await);
                             }
+
+                            // recognize gestures made with the button
+                            if let Update::Button { pressed } = update {
+                                for event in button.update(pressed, now) {
+                                    server.report(server.send_button(id, event).await);
+                                }
+                            }
+
+                            // add up how far the toio has driven, and report it when it stops
+                            if let Update::MotorSpeed {
+                                left_speed,
+                                right_speed,
+                            } = update
+                            {
+                                let totals = {
+                                    let mut odometry = odometry.write().await;
+                                    odometry.update(left_speed, right_speed, now);
+                                    odometry.clone()
+                                };
+                                if left_speed == 0 && right_speed == 0 {
+                                    server.report(server.send_odometry(id, &totals).await);
+                                }
+                            }
+
+                            // name the card the toio was placed on once, until it leaves the card
+                            match update {
+                                Update::Standard { standard, .. } if card != Some(standard) => {
+                                    card = Some(standard);
+                                    if let Some(name) = cards.name(standard) {
+                                        server.report(server.send_card(id, &name).await);
+                                    }
+                                }
+                                Update::StandardMissed => card = None,
+                                _ => {}
+                            }
+
+                            // if the toio is steered from the host, steer it from its new position
+                            if let Update::Position {
+                                x_center,
+                                y_center,
+                                theta,
+                                ..
+                            } = update
+                            {
+                                // the other toios may be on other mats, so avoid them in the
+                                // world frame, in mat units
+                                let (x, y, heading) = match world {
+                                    Some(world) => {
+                                        (world.x / MM_PER_UNIT, world.y / MM_PER_UNIT, world.theta)
+                                    }
+                                    None => (x_center as f32, y_center as f32, theta as f32),
+                                };
+                                swarm.write().await.update(id, x, y);
+
+                                let step = controller.write().await.as_mut().map(|controller| {
+                                    let step = controller.update(
+                                        x_center as f32,
+                                        y_center as f32,
+                                        theta as f32,
+                                    );
+                                    let percent = match controller {
+                                        Controller::Path(follower) => {
+                                            Some((follower.progress() * 100.0) as i32)
+                                        }
+                                        Controller::GoTo(_) => None,
+                                    };
+                                    (step, percent)
+                                });
+
+                                // steer around the other toios if avoidance is on
+                                let step = match step {
+                                    Some((step, percent)) => {
+                                        let swarm = swarm.read().await;
+                                        Some((swarm.avoid(id, heading, step), percent))
+                                    }
+                                    None => None,
+                                };
+
+                                match step {
+                                    Some((FollowStep::Drive { left, right }, percent)) => {
+                                        dispatch(
+                                            &connected,
+                                            id,
+                                            Command::wheels(left, right),
+                                            None,
+                                        )
+                                        .await;
+                                        if let Some(percent) = percent {
+                                            if path_percent != Some(percent) {
+                                                path_percent = Some(percent);
+                                                server.report(
+                                                    server.send_path_progress(id, percent).await,
+                                                );
+                                            }
+                                        }
+                                    }
+                                    Some((FollowStep::Arrived, percent)) => {
+                                        *controller.write().await = None;
+                                        path_percent = None;
+                                        dispatch(&connected, id, Command::wheels(0, 0), None).await;
+                                        if percent.is_some() {
+                                            server.report(server.send_path_progress(id, 100).await);
+                                            server.report(server.send_path_done(id).await);
+                                        } else {
+                                            server.report(server.send_goto_done(id, 0).await);
+                                        }
+                                    }
+                                    None => path_percent = None,
+                                }
+                            }
+
+                            // stop a toio following a path while it is off the mat, and give
+                            // a toio going to a point a little time to find the mat again
+                            if let Update::PositionMissed = update {
+                                swarm.write().await.remove(id);
+                                server.leave_formation(id).await;
+
+                                let missed = controller.read().await.clone();
+                                match missed {
+                                    Some(Controller::Path(_)) => {
+                                        dispatch(&connected, id, Command::wheels(0, 0), None).await;
+                                    }
+                                    Some(Controller::GoTo(goto)) => {
+                                        let connected = connected.clone();
+                                        let controller = controller.clone();
+                                        let server = server.clone();
+                                        tokio::spawn(async move {
+                                            sleep(goto.options().missed_timeout).await;
+                                            let mut controller = controller.write().await;
+                                            if let Some(Controller::GoTo(goto)) = &*controller {
+                                                if goto.lost() {
+                                                    *controller = None;
+                                                    drop(controller);
+                                                    dispatch(
+                                                        &connected,
+                                                        id,
+                                                        Command::wheels(0, 0),
+                                                        None,
+                                                    )
+                                                    .await;
+                                                    server
+                                                        .report(server.send_goto_done(id, 2).await);
+                                                }
+                                            }
+                                        });
+                                    }
+                                    None => {}
+                                }
+                            }
+
+                            // record time of update
+                            let mut last_update = last_update.write().await;
+                            *last_update = Some(SystemTime::now());
+
+                            #[cfg(feature = "websocket")]
+                            if let Some(websocket) = &websocket {
+                                websocket.send_update(id, &update, world);
+                            }
+                            server.report(server.send_update(id, update, world).await);
                         }
+
+                        // a toio that disconnects will not reach its goal in a formation
+                        server.leave_formation(id).await;
                     });
 
                     toio.add_channel(toio_channel);
@@ -207,13 +649,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let id = toio.id.clone();
             let connected = toio.is_connected().await;
 
+            // read everything the toio has reported at once
+            let state = toio.state.read().await.clone();
+
             // get battery level
-            let battery_string = if let Some(level) = *toio.battery.read().await {
+            let battery_string = if let Some(level) = state.battery {
                 format!("{}", level)
             } else {
                 "N/A".to_string()
             };
 
+            // get position on the mat
+            let position_string = if state.on_mat {
+                format!("{},{} {}°", state.x, state.y, state.theta)
+            } else {
+                "Off mat".to_string()
+            };
+
             // get time of last update
             let last_update_string = if let Some(last) = *toio.last_update.read().await {
                 if let Ok(time) = last.elapsed() {
@@ -248,26 +700,64 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 "N/A".to_string()
             };
 
-            (
+            // get number of notifications that failed to decode
+            let errors_string = format!("{}", *toio.decode_errors.read().await);
+
+            // get distance travelled, angle turned and time moving
+            let odometry = toio.odometry.read().await.at(Instant::now());
+            let odometry_string = format!(
+                "{:.2}m {:.0}° {}s",
+                odometry.distance * MM_PER_UNIT / 1000.0,
+                odometry.heading,
+                odometry.moving.as_secs()
+            );
+
+            ToioInfo {
                 name,
                 id,
-                battery_string,
-                last_update_string,
-                last_command_string,
+                battery: battery_string,
+                last_update: last_update_string,
+                last_command: last_command_string,
                 connected,
-            )
+                errors: errors_string,
+                odometry: odometry_string,
+                position: position_string,
+            }
         }))
         .await;
 
         //  update UI
         if let Some(ref mut toio_ui) = terminal {
-            toio_ui.draw(ui(toio_info, args.axlab_id.clone()))?;
+            let server_errors = server.get_errors().to_string();
+            toio_ui.draw(ui(toio_info, args.axlab_id.clone(), server_errors))?;
         }
 
         // // exit terminal if "Q" key is pressed
         if handle_events()? {
+            server.shutdown();
+            #[cfg(feature = "websocket")]
+            if let Some(websocket) = &websocket {
+                websocket.shutdown();
+            }
+            join_all(listeners).await;
             exit_terminal()?;
             process::exit(0);
         }
     }
 }
+
+/// Waits for the next tick of an interval, or forever if there is none
+async fn tick(interval: &mut Option<Interval>) -> tokio::time::Instant {
+    return match interval {
+        Some(interval) => interval.tick().await,
+        None => std::future::pending().await,
+    };
+}
+
+/// Waits until the given time, or forever if there is none
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
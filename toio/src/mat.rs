@@ -0,0 +1,566 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Size of one mat unit in millimetres, which is the same for every mat
+pub const MM_PER_UNIT: f32 = 560.0 / 411.0;
+
+/// Known toio mats, each printed with its own range of coordinates as listed at
+/// https://toio.github.io/toio-spec/en/docs/hardware_position_id
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatType {
+    /// Ring side of the mat from toio Collection
+    Ring,
+    /// Coloured tile side of the mat from toio Collection
+    Tiles,
+    /// Simple mat that comes with the toio Core Cube set
+    Simple,
+    /// Mat from Gesundroid
+    Gesundroid,
+    /// One of the 12 toio developer mats, numbered from 1
+    Developer(u8),
+}
+
+/// Range of coordinates printed on a mat, inclusive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatBounds {
+    pub x_min: u16,
+    pub y_min: u16,
+    pub x_max: u16,
+    pub y_max: u16,
+}
+
+/// Position of a mat in a layout, from the origin of the layout to the top left
+/// corner of the mat in millimetres, and the angle the mat is turned around
+/// that corner in degrees, clockwise like the angle of a toio
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatPlacement {
+    pub mat: MatType,
+    pub x: f32,
+    pub y: f32,
+    pub rotation: f32,
+}
+
+/// Mats placed on a table in a shared world frame measured in millimetres.
+/// Mats can share coordinates, such as a grid of simple mats, in which case
+/// each toio is followed from one mat to the next with [`MatLayout::locate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatLayout {
+    pub placements: Vec<MatPlacement>,
+}
+
+/// Position and angle of a toio in the world frame of a layout, in millimetres
+/// and degrees, with the index of the mat it is on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct WorldPosition {
+    pub mat: usize,
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+}
+
+/// Units positions are given in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Units {
+    /// Coordinates printed on the mat
+    #[default]
+    Raw,
+    /// Millimetres from the top left corner of the layout
+    Millimetres,
+    /// From 0 to 1 across the width and height of the layout
+    Normalized,
+}
+
+impl MatType {
+    pub fn bounds(&self) -> MatBounds {
+        let (x_min, y_min, x_max, y_max) = match self {
+            MatType::Ring => (45, 45, 455, 455),
+            MatType::Tiles => (545, 45, 955, 455),
+            MatType::Simple => (98, 142, 402, 358),
+            MatType::Gesundroid => (1050, 45, 1460, 455),
+            MatType::Developer(number) => {
+                // numbered down each column of 4 mats, then across 3 columns
+                let index = (*number).clamp(1, 12) as u16 - 1;
+                let (x_min, y_min) = (34 + index / 4 * 306, 35 + index % 4 * 216);
+                (x_min, y_min, x_min + 305, y_min + 215)
+            }
+        };
+        return MatBounds {
+            x_min,
+            y_min,
+            x_max,
+            y_max,
+        };
+    }
+
+    /// Width and height of the printed coordinates in millimetres
+    pub fn size(&self) -> (f32, f32) {
+        let bounds = self.bounds();
+        return (
+            (bounds.x_max - bounds.x_min) as f32 * MM_PER_UNIT,
+            (bounds.y_max - bounds.y_min) as f32 * MM_PER_UNIT,
+        );
+    }
+}
+
+impl MatBounds {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        return (self.x_min..=self.x_max).contains(&x) && (self.y_min..=self.y_max).contains(&y);
+    }
+}
+
+impl MatPlacement {
+    /// Converts mat coordinates into millimetres in the world frame
+    pub fn to_world(&self, x: u16, y: u16) -> (f32, f32) {
+        let bounds = self.mat.bounds();
+        let local = self.to_world_vector(
+            x as f32 - bounds.x_min as f32,
+            y as f32 - bounds.y_min as f32,
+        );
+        return (self.x + local.0, self.y + local.1);
+    }
+
+    /// Converts a distance along each axis of the mat in mat units, such as a
+    /// velocity, into millimetres along the axes of the world frame
+    pub fn to_world_vector(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = (x * MM_PER_UNIT, y * MM_PER_UNIT);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        return (x * cos - y * sin, x * sin + y * cos);
+    }
+
+    /// Converts millimetres in the world frame into mat coordinates, if the
+    /// point is on the mat
+    pub fn from_world(&self, x: f32, y: f32) -> Option<(u16, u16)> {
+        let (dx, dy) = (x - self.x, y - self.y);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let local = (dx * cos + dy * sin, dy * cos - dx * sin);
+
+        let bounds = self.mat.bounds();
+        let x = bounds.x_min as i32 + (local.0 / MM_PER_UNIT).round() as i32;
+        let y = bounds.y_min as i32 + (local.1 / MM_PER_UNIT).round() as i32;
+        if !(bounds.x_min as i32..=bounds.x_max as i32).contains(&x)
+            || !(bounds.y_min as i32..=bounds.y_max as i32).contains(&y)
+        {
+            return None;
+        }
+        return Some((x as u16, y as u16));
+    }
+
+    /// Converts an angle on the mat in degrees into the world frame
+    pub fn to_world_angle(&self, theta: f32) -> f32 {
+        return (theta + self.rotation).rem_euclid(360.0);
+    }
+
+    /// Converts an angle in the world frame in degrees into one on the mat
+    pub fn from_world_angle(&self, theta: f32) -> f32 {
+        return (theta - self.rotation).rem_euclid(360.0);
+    }
+}
+
+impl MatLayout {
+    /// Creates a layout from placed mats, or returns an error if there are none
+    pub fn new(placements: Vec<MatPlacement>) -> Result<MatLayout, String> {
+        if placements.is_empty() {
+            return Err("layout has no mats".to_string());
+        }
+        return Ok(MatLayout { placements });
+    }
+
+    /// Lays out mats in rows of the given number of columns, in cells as large
+    /// as the largest mat, or returns an error if there are none
+    pub fn grid(mats: &[MatType], columns: usize) -> Result<MatLayout, String> {
+        let columns = columns.max(1);
+        let (width, height) = mats.iter().fold((0.0f32, 0.0f32), |size, mat| {
+            (size.0.max(mat.size().0), size.1.max(mat.size().1))
+        });
+
+        return MatLayout::new(
+            mats.iter()
+                .enumerate()
+                .map(|(i, mat)| MatPlacement {
+                    mat: *mat,
+                    x: (i % columns) as f32 * width,
+                    y: (i / columns) as f32 * height,
+                    rotation: 0.0,
+                })
+                .collect(),
+        );
+    }
+
+    /// Width and height of the layout in millimetres, from the origin to the
+    /// furthest corner of any mat
+    pub fn size(&self) -> (f32, f32) {
+        return self.placements.iter().fold((0.0, 0.0), |size, placement| {
+            let bounds = placement.mat.bounds();
+            [
+                (bounds.x_min, bounds.y_min),
+                (bounds.x_max, bounds.y_min),
+                (bounds.x_min, bounds.y_max),
+                (bounds.x_max, bounds.y_max),
+            ]
+            .iter()
+            .map(|(x, y)| placement.to_world(*x, *y))
+            .fold(size, |size, corner| {
+                (f32::max(size.0, corner.0), f32::max(size.1, corner.1))
+            })
+        });
+    }
+
+    /// Places mat coordinates in the world frame, if they are on a mat in the
+    /// layout. When several mats have the coordinates, the toio is taken to be
+    /// on whichever of them puts it closest to where it was last, so that it
+    /// is followed as it drives from one mat to the next. A toio that has not
+    /// been placed before is taken to be on the first of them.
+    pub fn locate(
+        &self,
+        x: u16,
+        y: u16,
+        theta: u16,
+        last: Option<&WorldPosition>,
+    ) -> Option<WorldPosition> {
+        let mut candidates = self
+            .placements
+            .iter()
+            .enumerate()
+            .filter(|(_, placement)| placement.mat.bounds().contains(x, y))
+            .map(|(mat, placement)| {
+                let (world_x, world_y) = placement.to_world(x, y);
+                WorldPosition {
+                    mat,
+                    x: world_x,
+                    y: world_y,
+                    theta: placement.to_world_angle(theta as f32),
+                }
+            });
+
+        let Some(last) = last else {
+            return candidates.next();
+        };
+        let gap = |position: &WorldPosition| (position.x - last.x).hypot(position.y - last.y);
+        return candidates.min_by(|a, b| gap(a).total_cmp(&gap(b)));
+    }
+
+    /// Places a position in the given units in the world frame, if it is on a
+    /// mat in the layout. In raw units, the position is on the first mat with
+    /// the coordinates and its angle is on that mat, while in other units the
+    /// angle is already in the world frame.
+    pub fn place(&self, x: f32, y: f32, theta: f32, units: Units) -> Option<WorldPosition> {
+        let mat = self.find(x, y, units)?;
+        let placement = &self.placements[mat];
+        let (x, y, theta) = match units {
+            Units::Raw => {
+                let (x, y) = placement.to_world(x.round() as u16, y.round() as u16);
+                (x, y, placement.to_world_angle(theta))
+            }
+            Units::Millimetres => (x, y, theta.rem_euclid(360.0)),
+            Units::Normalized => {
+                let (width, height) = self.size();
+                (x * width, y * height, theta.rem_euclid(360.0))
+            }
+        };
+        return Some(WorldPosition { mat, x, y, theta });
+    }
+
+    /// Converts a position in the world frame into the given units, which are
+    /// millimetres or normalized, or returns it unchanged in raw units
+    pub fn world_to_units(&self, x: f32, y: f32, units: Units) -> (f32, f32) {
+        if units == Units::Normalized {
+            let (width, height) = self.size();
+            return (x / width, y / height);
+        }
+        return (x, y);
+    }
+
+    /// Index of the mat a position in the given units is on, if any. In raw
+    /// units, this is the first mat with the coordinates.
+    pub fn find(&self, x: f32, y: f32, units: Units) -> Option<usize> {
+        let mm = match units {
+            Units::Raw => {
+                let (x, y) = (x.round() as u16, y.round() as u16);
+                return self
+                    .placements
+                    .iter()
+                    .position(|placement| placement.mat.bounds().contains(x, y));
+            }
+            Units::Millimetres => (x, y),
+            Units::Normalized => {
+                let (width, height) = self.size();
+                (x * width, y * height)
+            }
+        };
+
+        return self
+            .placements
+            .iter()
+            .position(|placement| placement.from_world(mm.0, mm.1).is_some());
+    }
+
+    /// Converts mat coordinates into the given units, if they are on a mat in
+    /// the layout, taking them to be on the first mat with the coordinates
+    pub fn to_units(&self, x: u16, y: u16, units: Units) -> Option<(f32, f32)> {
+        if units == Units::Raw {
+            return Some((x as f32, y as f32));
+        }
+
+        let placement = &self.placements[self.find(x as f32, y as f32, Units::Raw)?];
+        let mm = placement.to_world(x, y);
+
+        if units == Units::Normalized {
+            let (width, height) = self.size();
+            return Some((mm.0 / width, mm.1 / height));
+        }
+        return Some(mm);
+    }
+
+    /// Converts a position in the given units into mat coordinates, if it is
+    /// on a mat in the layout
+    pub fn from_units(&self, x: f32, y: f32, units: Units) -> Option<(u16, u16)> {
+        let mm = match units {
+            Units::Raw => return Some((x.round() as u16, y.round() as u16)),
+            Units::Millimetres => (x, y),
+            Units::Normalized => {
+                let (width, height) = self.size();
+                (x * width, y * height)
+            }
+        };
+
+        let placement = &self.placements[self.find(x, y, units)?];
+        return placement.from_world(mm.0, mm.1);
+    }
+}
+
+impl Default for MatLayout {
+    fn default() -> MatLayout {
+        return MatLayout {
+            placements: vec![MatPlacement {
+                mat: MatType::Ring,
+                x: 0.0,
+                y: 0.0,
+                rotation: 0.0,
+            }],
+        };
+    }
+}
+
+impl FromStr for MatLayout {
+    type Err = String;
+
+    /// Parses a layout file, with a line for each mat giving its name, the
+    /// position of its top left corner in millimetres and optionally its
+    /// rotation in degrees, such as `simple 0 0 90`. Blank lines and lines
+    /// starting with `#` are skipped.
+    fn from_str(text: &str) -> Result<MatLayout, String> {
+        let mut placements = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if !(3..=4).contains(&fields.len()) {
+                return Err(format!("line {}: expected mat x y [rotation]", number + 1));
+            }
+            let number_at = |i: usize| -> Result<f32, String> {
+                return fields.get(i).map_or(Ok(0.0), |field| {
+                    field
+                        .parse()
+                        .map_err(|_| format!("line {}: invalid number {}", number + 1, field))
+                });
+            };
+
+            placements.push(MatPlacement {
+                mat: fields[0]
+                    .parse()
+                    .map_err(|err| format!("line {}: {}", number + 1, err))?,
+                x: number_at(1)?,
+                y: number_at(2)?,
+                rotation: number_at(3)?,
+            });
+        }
+
+        return MatLayout::new(placements);
+    }
+}
+
+impl FromStr for MatType {
+    type Err = String;
+
+    /// Parses the name of a mat, such as `ring`, `simple` or `developer3`
+    fn from_str(name: &str) -> Result<MatType, String> {
+        return match name {
+            "ring" => Ok(MatType::Ring),
+            "tiles" => Ok(MatType::Tiles),
+            "simple" => Ok(MatType::Simple),
+            "gesundroid" => Ok(MatType::Gesundroid),
+            _ => match name.strip_prefix("developer").map(str::parse::<u8>) {
+                Some(Ok(number)) if (1..=12).contains(&number) => Ok(MatType::Developer(number)),
+                _ => Err(format!("unknown mat {}", name)),
+            },
+        };
+    }
+}
+
+impl fmt::Display for MatType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatType::Ring => write!(f, "ring"),
+            MatType::Tiles => write!(f, "tiles"),
+            MatType::Simple => write!(f, "simple"),
+            MatType::Gesundroid => write!(f, "gesundroid"),
+            MatType::Developer(number) => write!(f, "developer{}", number),
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    /// Parses `raw`, `mm` or `normalized`
+    fn from_str(name: &str) -> Result<Units, String> {
+        return match name {
+            "raw" => Ok(Units::Raw),
+            "mm" => Ok(Units::Millimetres),
+            "normalized" => Ok(Units::Normalized),
+            _ => Err(format!("unknown units {}", name)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mat_names() {
+        for name in [
+            "ring",
+            "tiles",
+            "simple",
+            "gesundroid",
+            "developer1",
+            "developer12",
+        ] {
+            assert_eq!(name.parse::<MatType>().unwrap().to_string(), name);
+        }
+        assert!("developer13".parse::<MatType>().is_err());
+        assert!("carpet".parse::<MatType>().is_err());
+    }
+
+    #[test]
+    fn converts_positions_across_a_grid() {
+        let layout = MatLayout::grid(&[MatType::Ring, MatType::Tiles], 2).unwrap();
+        let (width, height) = MatType::Ring.size();
+        assert_eq!(layout.size(), (2.0 * width, height));
+
+        let (x, y) = layout.to_units(545, 455, Units::Millimetres).unwrap();
+        assert_eq!((x, y), (width, height));
+        assert_eq!(
+            layout.to_units(955, 45, Units::Normalized),
+            Some((1.0, 0.0))
+        );
+        assert_eq!(layout.to_units(500, 100, Units::Millimetres), None);
+
+        for (x, y) in [(45, 45), (300, 200), (600, 455), (955, 45)] {
+            for units in [Units::Raw, Units::Millimetres, Units::Normalized] {
+                let (ux, uy) = layout.to_units(x, y, units).unwrap();
+                assert_eq!(layout.from_units(ux, uy, units), Some((x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn converts_positions_on_rotated_mats() {
+        let layout: MatLayout = "# two mats\ntiles 0 0\n\ndeveloper1 900 0 90\n"
+            .parse()
+            .unwrap();
+        let (_, height) = MatType::Developer(1).size();
+        let size = layout.size();
+        assert!((size.0 - 900.0).abs() < 1e-3);
+        assert!((size.1 - MatType::Tiles.size().1).abs() < 1e-3);
+
+        // the top left corner of the turned mat is at its offset, and its
+        // bottom left corner is to the left of it
+        let turned = layout.placements[1];
+        let (x, y) = turned.to_world(34, 35);
+        assert!((x - 900.0).abs() < 1e-3 && y.abs() < 1e-3);
+        let (x, y) = turned.to_world(34, 250);
+        assert!((x - (900.0 - height)).abs() < 1e-3 && y.abs() < 1e-3);
+        let (x, y) = turned.to_world_vector(10.0, 0.0);
+        assert!(x.abs() < 1e-3 && (y - 10.0 * MM_PER_UNIT).abs() < 1e-3);
+        assert_eq!(turned.to_world_angle(0.0), 90.0);
+        assert_eq!(turned.from_world_angle(45.0), 315.0);
+
+        assert_eq!(layout.find(850.0, 100.0, Units::Millimetres), Some(1));
+        assert_eq!(
+            layout.from_units(850.0, 100.0, Units::Millimetres),
+            turned.from_world(850.0, 100.0)
+        );
+        assert_eq!(layout.from_units(580.0, 100.0, Units::Millimetres), None);
+
+        // goals are placed in the world frame with their angle in it
+        let goal = layout.place(34.0, 35.0, 0.0, Units::Raw).unwrap();
+        assert_eq!((goal.mat, goal.theta), (1, 90.0));
+        assert!((goal.x - 900.0).abs() < 1e-3 && goal.y.abs() < 1e-3);
+        let goal = layout.place(850.0, 100.0, -45.0, Units::Millimetres);
+        assert_eq!(
+            goal,
+            Some(WorldPosition {
+                mat: 1,
+                x: 850.0,
+                y: 100.0,
+                theta: 315.0
+            })
+        );
+        assert_eq!(layout.place(580.0, 100.0, 0.0, Units::Millimetres), None);
+        assert!("simple 0".parse::<MatLayout>().is_err());
+        assert!("".parse::<MatLayout>().is_err());
+    }
+
+    #[test]
+    fn follows_toios_across_mats_with_the_same_coordinates() {
+        let layout = MatLayout::grid(&[MatType::Simple; 4], 2).unwrap();
+        let (width, height) = MatType::Simple.size();
+        assert!(MatLayout::grid(&[], 2).is_err());
+
+        // a toio first seen on a simple mat is on the first one
+        let start = layout.locate(300, 200, 0, None).unwrap();
+        assert_eq!(start.mat, 0);
+
+        // driving right off the first mat onto the left edge of the second
+        let edge = layout.locate(400, 200, 0, Some(&start)).unwrap();
+        assert_eq!(edge.mat, 0);
+        let across = layout.locate(100, 200, 0, Some(&edge)).unwrap();
+        assert_eq!(across.mat, 1);
+        assert!((across.x - (width + 2.0 * MM_PER_UNIT)).abs() < 1e-3);
+
+        // then down onto the mat below it, and staying there
+        let edge = layout.locate(200, 356, 0, Some(&across)).unwrap();
+        let below = layout.locate(200, 144, 90, Some(&edge)).unwrap();
+        assert_eq!(below.mat, 3);
+        assert!((below.y - (height + 2.0 * MM_PER_UNIT)).abs() < 1e-3);
+        assert_eq!(below.theta, 90.0);
+        let still = layout.locate(210, 160, 90, Some(&below)).unwrap();
+        assert_eq!(still.mat, 3);
+
+        assert_eq!(layout.locate(50, 50, 0, Some(&still)), None);
+        assert_eq!(
+            layout.world_to_units(below.x, below.y, Units::Normalized),
+            (below.x / (2.0 * width), below.y / (2.0 * height))
+        );
+
+        // targets in millimetres are on whichever mat is at that point
+        let (x, y) = layout
+            .from_units(1.5 * width, 1.5 * height, Units::Millimetres)
+            .unwrap();
+        assert_eq!(
+            layout.find(1.5 * width, 1.5 * height, Units::Millimetres),
+            Some(3)
+        );
+        assert_eq!((x, y), (250, 250));
+    }
+}
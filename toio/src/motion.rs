@@ -0,0 +1,308 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use btleplug::api::Peripheral;
+use futures::stream::StreamExt;
+use tokio::time::{sleep, timeout};
+
+use crate::codec::*;
+use crate::toio::*;
+
+/// Fastest speed the wheels can be driven at
+pub const MAX_WHEEL_SPEED: u8 = 115;
+
+/// Slowest speed the wheels turn at, where slower speeds leave them stopped
+pub const MIN_WHEEL_SPEED: u8 = 10;
+
+/// Distance between the wheels of a toio in mat units
+pub(crate) const WHEEL_BASE: f32 = 19.5;
+
+/// Rough distance in mat units a toio moves each second per unit of wheel speed
+pub(crate) const UNITS_PER_SPEED: f32 = 2.0;
+
+/// How a toio moves towards a target, as described at
+/// https://toio.github.io/toio-spec/en/docs/ble_motor#movement-type
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MoveType {
+    /// Rotate while moving, which may include driving backwards
+    #[default]
+    RotateWhileMoving,
+    /// Rotate while moving, without ever driving backwards
+    RotateWhileMovingForwards,
+    /// Rotate towards the target before moving
+    RotateThenMove,
+}
+
+/// How the speed of a toio changes on the way to a target, as described at
+/// https://toio.github.io/toio-spec/en/docs/ble_motor#motor-speed-change-types
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpeedChange {
+    #[default]
+    Constant,
+    /// Speed up gradually towards the target
+    Accelerate,
+    /// Slow down gradually towards the target
+    Decelerate,
+    /// Speed up gradually until halfway, then slow down towards the target
+    AccelerateThenDecelerate,
+}
+
+/// Options for moving a toio towards a target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveOptions {
+    /// Seconds before the toio gives up on reaching the target, where 0 means
+    /// the firmware default of 10 seconds
+    pub timeout: u8,
+    pub move_type: MoveType,
+    pub max_speed: u8,
+    pub speed_change: SpeedChange,
+}
+
+/// Reason a toio did not reach a target, from the response codes at
+/// https://toio.github.io/toio-spec/en/docs/ble_motor#responses-to-motor-control-with-target-specified
+#[derive(Debug)]
+pub enum MoveError {
+    /// The toio did not reach the target before its timeout
+    Timeout,
+    /// The toio lost its position on the mat
+    PositionMissed,
+    /// The combination of target and options is invalid
+    InvalidParameters,
+    /// The toio cannot move, for example because it is turned off
+    InvalidState,
+    /// Another motor command was sent before the target was reached
+    Overridden,
+    /// The firmware does not support moving to a target
+    Unsupported,
+    /// The firmware responded with a code that is not in the spec
+    Unknown(u8),
+    /// The toio did not respond before the timeout ran out
+    NoResponse,
+    /// The command could not be written to the toio
+    Write(btleplug::Error),
+}
+
+impl MoveType {
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            MoveType::RotateWhileMoving => 0x00,
+            MoveType::RotateWhileMovingForwards => 0x01,
+            MoveType::RotateThenMove => 0x02,
+        };
+    }
+}
+
+impl SpeedChange {
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            SpeedChange::Constant => 0x00,
+            SpeedChange::Accelerate => 0x01,
+            SpeedChange::Decelerate => 0x02,
+            SpeedChange::AccelerateThenDecelerate => 0x03,
+        };
+    }
+}
+
+impl Default for MoveOptions {
+    fn default() -> MoveOptions {
+        return MoveOptions {
+            timeout: 0,
+            move_type: MoveType::default(),
+            max_speed: 80,
+            speed_change: SpeedChange::default(),
+        };
+    }
+}
+
+impl MoveError {
+    /// Converts a response to a target command, where 0 means success
+    pub fn from_response(response: u8) -> Option<MoveError> {
+        return match response {
+            0x00 => None,
+            0x01 => Some(MoveError::Timeout),
+            0x02 => Some(MoveError::PositionMissed),
+            0x03 => Some(MoveError::InvalidParameters),
+            0x04 => Some(MoveError::InvalidState),
+            0x05 => Some(MoveError::Overridden),
+            0x06 => Some(MoveError::Unsupported),
+            code => Some(MoveError::Unknown(code)),
+        };
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::Timeout => write!(f, "toio did not reach the target in time"),
+            MoveError::PositionMissed => write!(f, "toio lost its position on the mat"),
+            MoveError::InvalidParameters => write!(f, "invalid target parameters"),
+            MoveError::InvalidState => write!(f, "toio is not able to move"),
+            MoveError::Overridden => write!(f, "another motor command was sent"),
+            MoveError::Unsupported => write!(f, "target commands are not supported"),
+            MoveError::Unknown(code) => write!(f, "unknown response code {}", code),
+            MoveError::NoResponse => write!(f, "toio did not respond"),
+            MoveError::Write(err) => write!(f, "error writing to toio: {}", err),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+impl From<btleplug::Error> for MoveError {
+    fn from(err: btleplug::Error) -> MoveError {
+        return MoveError::Write(err);
+    }
+}
+
+impl Command {
+    /// Drives each wheel at a signed speed, where negative speeds drive
+    /// backwards, until another motor command is sent
+    pub fn wheels(left: i16, right: i16) -> Command {
+        let (left_direction, left_speed) = wheel_speed(left);
+        let (right_direction, right_speed) = wheel_speed(right);
+
+        return Command::MotorControl {
+            left_direction,
+            left_speed,
+            right_direction,
+            right_speed,
+        };
+    }
+
+    /// Drives each wheel at a signed speed for a duration in units of 10ms,
+    /// where 0 means until another motor command is sent
+    pub fn wheels_for(left: i16, right: i16, duration: u8) -> Command {
+        let (left_direction, left_speed) = wheel_speed(left);
+        let (right_direction, right_speed) = wheel_speed(right);
+
+        return Command::MotorDuration {
+            left_direction,
+            left_speed,
+            right_direction,
+            right_speed,
+            duration,
+        };
+    }
+}
+
+impl ToioPeripheral {
+    /// Moves to a point on the mat, resolving once the toio reports that it
+    /// has arrived or why it could not
+    pub async fn move_to(
+        &self,
+        x: u16,
+        y: u16,
+        angle: TargetAngle,
+        options: MoveOptions,
+    ) -> Result<(), MoveError> {
+        let control = self.control_id();
+
+        // listen before sending so that the response cannot be missed
+        let mut notifications = self.peripheral.notifications().await?;
+
+        self.send_command(Command::MotorTarget {
+            control,
+            timeout: options.timeout,
+            move_type: options.move_type.to_byte(),
+            max_speed: options.max_speed,
+            speed_change: options.speed_change.to_byte(),
+            x_target: x,
+            y_target: y,
+            theta_target: angle,
+        })
+        .await?;
+
+        // wait a little longer than the toio does before it gives up
+        let seconds = if options.timeout == 0 {
+            10
+        } else {
+            options.timeout
+        };
+        let response = timeout(Duration::from_secs(seconds as u64 + 2), async {
+            while let Some(notification) = notifications.next().await {
+                if let Ok(Update::MotorTargetResponse {
+                    control: response_control,
+                    response,
+                }) = decode(notification.uuid, &notification.value)
+                {
+                    if response_control == control {
+                        return Some(response);
+                    }
+                }
+            }
+            return None;
+        })
+        .await;
+
+        return match response {
+            Ok(Some(response)) => match MoveError::from_response(response) {
+                Some(err) => Err(err),
+                None => Ok(()),
+            },
+            _ => Err(MoveError::NoResponse),
+        };
+    }
+
+    /// Rotates in place, resolving once the toio reports that it has finished
+    pub async fn rotate_to(
+        &self,
+        angle: TargetAngle,
+        options: MoveOptions,
+    ) -> Result<(), MoveError> {
+        // 0xFFFF keeps the position from when the command was sent
+        return self.move_to(0xFFFF, 0xFFFF, angle, options).await;
+    }
+
+    /// Drives each wheel at a signed speed, where negative speeds drive
+    /// backwards, until another motor command is sent
+    pub async fn drive(&self, left: i16, right: i16) -> Result<(), btleplug::Error> {
+        return self.send_command(Command::wheels(left, right)).await;
+    }
+
+    /// Drives each wheel at a signed speed for a duration, resolving once the
+    /// toio has stopped
+    pub async fn drive_for(
+        &self,
+        left: i16,
+        right: i16,
+        duration: Duration,
+    ) -> Result<(), btleplug::Error> {
+        // the toio counts durations in units of 10ms, where 0 means forever
+        let units = duration.as_millis() / 10;
+        if units == 0 {
+            return Ok(());
+        }
+
+        if units <= u8::MAX as u128 {
+            self.send_command(Command::wheels_for(left, right, units as u8))
+                .await?;
+            sleep(duration).await;
+        } else {
+            // too long for the toio to time itself
+            self.drive(left, right).await?;
+            sleep(duration).await;
+            self.stop().await?;
+        }
+
+        return Ok(());
+    }
+
+    /// Stops both wheels
+    pub async fn stop(&self) -> Result<(), btleplug::Error> {
+        return self.drive(0, 0).await;
+    }
+}
+
+/// Converts a signed wheel speed into a direction and speed. Speeds are
+/// clamped to [`MAX_WHEEL_SPEED`], and speeds below [`MIN_WHEEL_SPEED`] stop
+/// the wheel since the motor cannot turn that slowly.
+pub fn wheel_speed(speed: i16) -> (u8, u8) {
+    let direction = if speed < 0 { 0x02 } else { 0x01 };
+    let speed = speed.unsigned_abs().min(MAX_WHEEL_SPEED as u16) as u8;
+
+    if speed < MIN_WHEEL_SPEED {
+        return (0x01, 0);
+    }
+    return (direction, speed);
+}
@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+use crate::motion::{UNITS_PER_SPEED, WHEEL_BASE};
+use crate::toio::Command;
+
+/// How far a toio has driven, worked out from the speed of its wheels. Motor
+/// speed notifications are only sent when the speed changes, so each speed is
+/// counted until the next one arrives. The notifications do not say which way
+/// the wheels turn, so that is taken from the last motor command sent to the
+/// toio with [`Odometry::command`]. Wheels driven by the toio itself, such as
+/// for target commands, are counted as turning forwards.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Odometry {
+    /// Distance the middle of the toio has travelled in mat units, whichever
+    /// way it was going, which is how far its wheels have worn
+    pub distance: f32,
+    /// Distance the middle of the toio has travelled forwards in mat units,
+    /// less any distance travelled backwards
+    pub displacement: f32,
+    /// Angle the toio has turned clockwise in degrees, like the angle of its
+    /// position, less any angle turned anticlockwise
+    pub heading: f32,
+    /// Time the wheels have been turning
+    pub moving: Duration,
+    wheels: Option<(u8, u8, Instant)>,
+    /// Whether each wheel is turning backwards
+    backwards: (bool, bool),
+}
+
+impl Odometry {
+    pub fn new() -> Odometry {
+        return Odometry::default();
+    }
+
+    /// Counts the previous speed of the wheels up to now, then records the new
+    /// speed from a motor speed notification
+    pub fn update(&mut self, left: u8, right: u8, now: Instant) {
+        *self = self.at(now);
+        self.wheels = Some((left, right, now));
+    }
+
+    /// Counts the previous speed of the wheels up to now, then records which
+    /// way each wheel turns from a command sent to the toio. Other commands
+    /// than those that move the toio are ignored.
+    pub fn command(&mut self, command: &Command, now: Instant) {
+        let backwards = match command {
+            Command::MotorControl {
+                left_direction,
+                right_direction,
+                ..
+            }
+            | Command::MotorDuration {
+                left_direction,
+                right_direction,
+                ..
+            } => (*left_direction == 0x02, *right_direction == 0x02),
+            Command::MotorTarget { .. }
+            | Command::MultiTarget { .. }
+            | Command::MotorAcceleration { .. } => (false, false),
+            _ => return,
+        };
+
+        *self = self.at(now);
+        self.backwards = backwards;
+    }
+
+    /// Totals including the current speed of the wheels up to the given time
+    pub fn at(&self, now: Instant) -> Odometry {
+        let mut odometry = self.clone();
+        let Some((left_speed, right_speed, since)) = self.wheels else {
+            return odometry;
+        };
+
+        let signed = |speed: u8, backwards: bool| -> f32 {
+            return if backwards {
+                -(speed as f32)
+            } else {
+                speed as f32
+            };
+        };
+        let left = signed(left_speed, self.backwards.0);
+        let right = signed(right_speed, self.backwards.1);
+
+        let elapsed = now.saturating_duration_since(since);
+        let dt = elapsed.as_secs_f32();
+        odometry.distance += (left.abs() + right.abs()) / 2.0 * UNITS_PER_SPEED * dt;
+        odometry.displacement += (left + right) / 2.0 * UNITS_PER_SPEED * dt;
+        odometry.heading += ((left - right) * UNITS_PER_SPEED / WHEEL_BASE * dt).to_degrees();
+        if left_speed > 0 || right_speed > 0 {
+            odometry.moving += elapsed;
+        }
+        odometry.wheels = Some((left_speed, right_speed, now));
+        return odometry;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drives_straight_forwards_and_backwards() {
+        let start = Instant::now();
+        let mut odometry = Odometry::new();
+
+        odometry.update(50, 50, start);
+        let driven = odometry.at(start + Duration::from_secs(2));
+        assert!((driven.distance - 200.0).abs() < 1e-3);
+        assert!((driven.displacement - 200.0).abs() < 1e-3);
+        assert_eq!(driven.heading, 0.0);
+        assert_eq!(driven.moving, Duration::from_secs(2));
+
+        // backing up adds to the distance but undoes the displacement, and
+        // stopping adds nothing
+        odometry.command(&Command::wheels(-50, -50), start + Duration::from_secs(2));
+        odometry.update(0, 0, start + Duration::from_secs(4));
+        let stopped = odometry.at(start + Duration::from_secs(10));
+        assert!((stopped.distance - 400.0).abs() < 1e-3);
+        assert!(stopped.displacement.abs() < 1e-3);
+        assert_eq!(stopped.moving, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn turns_from_the_difference_between_the_wheels() {
+        let start = Instant::now();
+        let second = start + Duration::from_secs(1);
+
+        // turning on the spot clockwise, with the right wheel backwards
+        let mut odometry = Odometry::new();
+        odometry.command(&Command::wheels(20, -20), start);
+        odometry.update(20, 20, start);
+        let turned = odometry.at(second);
+        let expected = (80.0 / WHEEL_BASE).to_degrees();
+        assert!((turned.distance - 40.0).abs() < 1e-3);
+        assert!(turned.displacement.abs() < 1e-3);
+        assert!((turned.heading - expected).abs() < 1e-2);
+
+        // curving anticlockwise with the right wheel faster
+        let mut odometry = Odometry::new();
+        odometry.update(10, 30, start);
+        let curved = odometry.at(second);
+        assert!((curved.distance - 40.0).abs() < 1e-3);
+        assert!((curved.displacement - 40.0).abs() < 1e-3);
+        assert!((curved.heading + expected / 2.0).abs() < 1e-2);
+    }
+}
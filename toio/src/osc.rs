@@ -1,27 +1,138 @@
-use std::io::{self};
-use std::net::UdpSocket;
-
 use std::vec;
 
-use rosc::encoder;
 use rosc::{OscMessage, OscPacket, OscType};
 
-use crossterm::event::{self, Event, KeyCode};
+use toio::codec::{pack_angle, unpack_angle};
+use toio::*;
 
-use crate::toio::*;
+/// Something a client has asked the bridge to do with a toio
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Send a command to the toio
+    Command(Command),
+    /// Steer the toio from the host, or stop steering it when `None`
+    Steer(Option<Controller>),
+    /// Send something the bridge knows about the toio back to the client
+    Query(Query),
+}
 
-pub fn handle_events() -> io::Result<bool> {
-    if event::poll(std::time::Duration::from_millis(50))? {
-        if let Event::Key(key) = event::read()? {
-            if key.kind == event::KeyEventKind::Press && key.code == KeyCode::Char('q') {
-                return Ok(true);
-            }
-        }
-    }
-    Ok(false)
+/// Something a client has asked the bridge to report about a toio
+#[derive(Debug, PartialEq)]
+pub enum Query {
+    /// How far the toio has driven
+    Odometry,
+    /// Latest of everything the toio has reported
+    State,
+}
+
+/// Something a client has asked the bridge to change for every toio
+pub enum SessionAction {
+    /// Turn collision avoidance on or off, optionally with a new cube radius
+    Avoid { enabled: bool, radius: Option<f32> },
+    /// Send the toios on the mat to goals given as a position and an angle
+    Formation(Vec<(f32, f32, f32)>),
+    /// Lay out the mats in a grid with the given number of columns
+    Mats { mats: Vec<MatType>, columns: usize },
+    /// Give positions in different units
+    Units(Units),
+}
+
+/// Converts an OSC packet into an action for every toio, which do not take a
+/// toio ID. `/avoid enabled [radius]` turns collision avoidance between toios
+/// steered from the host on or off, where the radius of each cube is in mat
+/// units.
+///
+/// `/formation x y angle ...` sends the toios on the mat to the given goals,
+/// picking which toio goes to which goal so that they travel as little as
+/// possible. The goals are in the units picked with `/units`, and toios are
+/// matched to them in the world frame of the layout, so the toios and goals can
+/// be on different mats. Each toio that was given a goal is reported over
+/// `/formation/assign cube goal`, and `/formation/done arrived assigned` is
+/// sent once every toio has responded, left the mat or disconnected, or after
+/// 15 seconds, so that fewer than were assigned arrived when any failed. The
+/// toios drive to their goals by themselves, so `/avoid` does not apply.
+///
+/// `/mats columns name ...` declares the mats the toios are on, such as
+/// `/mats 2 simple simple simple simple` for a 2×2 tile of simple mats. Mats
+/// that print the same coordinates are told apart by following each toio as it
+/// drives from one to the next, so a toio lifted onto another of them is taken
+/// to be on whichever is closest to where it was. `/units raw|mm|normalized`
+/// picks the units of positions, in `/position` and `/state` and in the
+/// targets of commands.
+pub fn handle_session_packet(packet: &OscPacket) -> Option<SessionAction> {
+    let OscPacket::Message(msg) = packet else {
+        return None;
+    };
+    let vals: Vec<f32> = msg
+        .args
+        .iter()
+        .flat_map(|val| match val {
+            OscType::Int(i) => Some(*i as f32),
+            OscType::Float(f) => Some(*f),
+            OscType::Bool(b) => Some(*b as i32 as f32),
+            _ => None,
+        })
+        .collect();
+    let names: Vec<&str> = msg
+        .args
+        .iter()
+        .flat_map(|val| match val {
+            OscType::String(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    return match msg.addr.as_str() {
+        "/mats" => Some(SessionAction::Mats {
+            mats: names
+                .iter()
+                .map(|name| name.parse())
+                .collect::<Result<_, _>>()
+                .ok()?,
+            columns: *vals.first()? as usize,
+        }),
+        "/units" => Some(SessionAction::Units(names.first()?.parse().ok()?)),
+        "/avoid" => Some(SessionAction::Avoid {
+            enabled: *vals.first()? != 0.0,
+            radius: vals.get(1).copied(),
+        }),
+        "/formation" => Some(SessionAction::Formation(
+            vals.chunks_exact(3)
+                .map(|goal| (goal[0], goal[1], goal[2]))
+                .collect(),
+        )),
+        _ => None,
+    };
 }
 
-pub fn handle_packet(packet: OscPacket) -> Option<(usize, Command)> {
+/// Converts an OSC packet into an action for a toio. Any command address can be
+/// prefixed with `/seq` (e.g. `/seq/led`), in which case the argument after the
+/// toio ID is a sequence number that is acknowledged over `/ack` once the
+/// command has been delivered.
+///
+/// `/multitarget` appends its targets to those the toio is already moving to,
+/// while `/multitarget/overwrite` replaces them. Either can hold any number of
+/// targets, which are split into several packets when there are too many for
+/// one.
+///
+/// `/path cube speed lookahead x y ...` follows a path through every point, and
+/// `/path/bezier` follows a chain of cubic Bézier curves given as a start point
+/// followed by two control points and an end point for each curve. Progress is
+/// reported over `/path/progress` until the toio arrives and `/path/done` is
+/// sent. `/path/stop cube` stops following the path.
+///
+/// `/config/motorspeed cube enabled` turns motor speed notifications on or off,
+/// which odometry is built from. `/odometry cube` asks for the odometry of the
+/// toio, which is also sent whenever its wheels stop. `/state cube` asks for
+/// the latest of everything the toio has reported, as described for
+/// `encode_state`.
+///
+/// `/goto cube x y [max_speed tolerance distance_gain angle_gain]` steers the
+/// toio to a point from the host, where the arguments can be ints or floats.
+/// It keeps going through short gaps in the position of the toio, and reports
+/// `/goto/done` with a status of 0 once it arrives or 2 if the toio was off the
+/// mat for too long. `/goto/stop cube` stops steering it.
+pub fn handle_packet(packet: OscPacket) -> Option<(usize, Action, Option<u32>)> {
     match packet {
         OscPacket::Message(msg) => {
             let mut vals: Vec<i32> = msg
@@ -33,104 +144,330 @@ pub fn handle_packet(packet: OscPacket) -> Option<(usize, Command)> {
                 })
                 .collect();
 
+            // some addresses also take floats
+            let mut floats: Vec<f32> = msg
+                .args
+                .iter()
+                .flat_map(|val| match val {
+                    OscType::Int(i) => Some(*i as f32),
+                    OscType::Float(f) => Some(*f),
+                    _ => None,
+                })
+                .collect();
+
+            // extract sequence number, after which every argument is read by
+            // its position without the sequence number and must be checked
+            let (addr, seq) = match msg.addr.strip_prefix("/seq") {
+                Some(addr) if vals.len() > 1 => {
+                    floats.remove(1);
+                    (addr, Some(vals.remove(1) as u32))
+                }
+                _ => (msg.addr.as_str(), None),
+            };
+
             // extract command
-            let cmd: Option<Command> = match msg.addr.as_ref() {
-                "/motorbasic" => Some(Command::MotorControl {
-                    left_direction: vals[1] as u8,
-                    left_speed: vals[2] as u8,
-                    right_direction: vals[3] as u8,
-                    right_speed: vals[4] as u8,
-                }),
-                "/motorduration" => Some(Command::MotorDuration {
-                    left_direction: vals[1] as u8,
-                    left_speed: vals[2] as u8,
-                    right_direction: vals[3] as u8,
-                    right_speed: vals[4] as u8,
-                    duration: vals[5] as u8,
-                }),
-                "/motortarget" => Some(Command::MotorTarget {
-                    control: vals[1] as u8,
-                    timeout: vals[2] as u8,
-                    move_type: vals[3] as u8,
-                    max_speed: vals[4] as u8,
-                    speed_change: vals[5] as u8,
-                    x_target: vals[6] as u16,
-                    y_target: vals[7] as u16,
-                    theta_target: vals[8] as u16,
-                }),
-                "/motoracceleration" => Some(Command::MotorAcceleration {
-                    velocity: vals[1] as u8,
-                    acceleration: vals[2] as u8,
-                    rotational_velocity: vals[3] as u16,
-                    rotational_direction: vals[4] as u8,
-                    direction: vals[5] as u8,
-                    priority: vals[6] as u8,
-                    duration: vals[7] as u8,
-                }),
-                "/multitarget" => Some(Command::MultiTarget {
-                    control: vals[1] as u8,
-                    timeout: vals[2] as u8,
-                    move_type: vals[3] as u8,
-                    max_speed: vals[4] as u8,
-                    speed_change: vals[5] as u8,
-                    op_add: 1,
-                    targets: vals
-                        .split_off(6)
-                        .chunks(3)
-                        .map(|target| TargetCommand {
-                            x_target: target[0] as u16,
-                            y_target: target[1] as u16,
-                            theta_target: target[2] as u16,
-                        })
-                        .collect(),
-                }),
-                "/led" => Some(Command::Led {
-                    duration: vals[1] as u8,
-                    red: vals[2] as u8,
-                    green: vals[3] as u8,
-                    blue: vals[4] as u8,
-                }),
-                "/multiLed" => Some(Command::MultiLed {
-                    repetitions: vals[1] as u8,
-                    lights: vals
-                        .split_off(2)
-                        .chunks(4)
-                        .map(|light| LedCommand {
-                            duration: light[0] as u8,
-                            red: light[1] as u8,
-                            green: light[2] as u8,
-                            blue: light[3] as u8,
-                        })
-                        .collect(),
-                }),
-                "/sound" => Some(Command::Sound {
-                    sound_effect: vals[1] as u8,
-                    volume: vals[2] as u8,
-                }),
-                "/midi" => Some(Command::Midi {
-                    repetitions: vals[1] as u8,
-                    notes: vals
-                        .split_off(2)
-                        .chunks(3)
-                        .map(|note| MidiCommand {
-                            duration: note[0] as u8,
-                            note: note[1] as u8,
-                            volume: note[2] as u8,
+            let cmd: Option<Command> = match addr {
+                "/motorbasic" => {
+                    let args = vals.get(1..5)?;
+                    Some(Command::MotorControl {
+                        left_direction: args[0] as u8,
+                        left_speed: args[1] as u8,
+                        right_direction: args[2] as u8,
+                        right_speed: args[3] as u8,
+                    })
+                }
+                "/motorduration" => {
+                    let args = vals.get(1..6)?;
+                    Some(Command::MotorDuration {
+                        left_direction: args[0] as u8,
+                        left_speed: args[1] as u8,
+                        right_direction: args[2] as u8,
+                        right_speed: args[3] as u8,
+                        duration: args[4] as u8,
+                    })
+                }
+                "/wheels" => {
+                    let wheels = vals.get(1..3)?;
+                    let left = wheels[0].clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                    let right = wheels[1].clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                    match vals.get(3) {
+                        Some(duration) => Some(Command::wheels_for(left, right, *duration as u8)),
+                        None => Some(Command::wheels(left, right)),
+                    }
+                }
+                "/motortarget" | "/motortarget/absolute" | "/motortarget/relative" => {
+                    let args = vals.get(1..9)?;
+                    Some(Command::MotorTarget {
+                        control: args[0] as u8,
+                        timeout: args[1] as u8,
+                        move_type: args[2] as u8,
+                        max_speed: args[3] as u8,
+                        speed_change: args[4] as u8,
+                        x_target: args[5] as u16,
+                        y_target: args[6] as u16,
+                        theta_target: target_angle(addr, args[7])?,
+                    })
+                }
+                "/motoracceleration" => {
+                    let args = vals.get(1..8)?;
+                    Some(Command::MotorAcceleration {
+                        velocity: args[0] as u8,
+                        acceleration: args[1] as u8,
+                        rotational_velocity: args[2] as u16,
+                        rotational_direction: args[3] as u8,
+                        direction: args[4] as u8,
+                        priority: args[5] as u8,
+                        duration: args[6] as u8,
+                    })
+                }
+                "/multitarget"
+                | "/multitarget/absolute"
+                | "/multitarget/relative"
+                | "/multitarget/overwrite"
+                | "/multitarget/overwrite/absolute"
+                | "/multitarget/overwrite/relative" => {
+                    let args = vals.get(1..6)?;
+                    // every target is a position and an angle
+                    let targets = vals[6..].chunks_exact(3);
+                    if targets.len() == 0 || !targets.remainder().is_empty() {
+                        return None;
+                    }
+                    let targets = targets
+                        .map(|target| {
+                            Some(TargetCommand {
+                                x_target: target[0] as u16,
+                                y_target: target[1] as u16,
+                                theta_target: target_angle(addr, target[2])?,
+                            })
                         })
-                        .collect(),
+                        .collect::<Option<Vec<_>>>()?;
+                    Some(Command::MultiTarget {
+                        control: args[0] as u8,
+                        timeout: args[1] as u8,
+                        move_type: args[2] as u8,
+                        max_speed: args[3] as u8,
+                        speed_change: args[4] as u8,
+                        op_add: if addr.starts_with("/multitarget/overwrite") {
+                            WriteMode::Overwrite
+                        } else {
+                            WriteMode::Append
+                        },
+                        targets,
+                    })
+                }
+                "/config/motorspeed" => Some(Command::MotorSpeedConfig {
+                    enabled: *vals.get(1)? != 0,
                 }),
+                "/led" => {
+                    let args = vals.get(1..5)?;
+                    Some(Command::Led {
+                        duration: args[0] as u8,
+                        red: args[1] as u8,
+                        green: args[2] as u8,
+                        blue: args[3] as u8,
+                    })
+                }
+                "/multiLed" => {
+                    let lights = vals.get(2..)?.chunks_exact(4);
+                    if !lights.remainder().is_empty() {
+                        return None;
+                    }
+                    Some(Command::MultiLed {
+                        repetitions: vals[1] as u8,
+                        lights: lights
+                            .map(|light| LedCommand {
+                                duration: light[0] as u8,
+                                red: light[1] as u8,
+                                green: light[2] as u8,
+                                blue: light[3] as u8,
+                            })
+                            .collect(),
+                    })
+                }
+                "/sound" => {
+                    let args = vals.get(1..3)?;
+                    Some(Command::Sound {
+                        sound_effect: args[0] as u8,
+                        volume: args[1] as u8,
+                    })
+                }
+                "/midi" => {
+                    let notes = vals.get(2..)?.chunks_exact(3);
+                    if !notes.remainder().is_empty() {
+                        return None;
+                    }
+                    Some(Command::Midi {
+                        repetitions: vals[1] as u8,
+                        notes: notes
+                            .map(|note| MidiCommand {
+                                duration: note[0] as u8,
+                                note: note[1] as u8,
+                                volume: note[2] as u8,
+                            })
+                            .collect(),
+                    })
+                }
 
                 _ => None,
             };
 
-            // Return pair of (toioID, pair)
-            return cmd.map(|cmd| (vals[0] as usize, cmd));
+            // extract actions that are handled by the bridge
+            let action: Option<Action> = match addr {
+                "/path" | "/path/bezier" => {
+                    let (speed, lookahead) = (*vals.get(1)?, *vals.get(2)?);
+                    let points: Vec<(f32, f32)> = vals
+                        .get(3..)?
+                        .chunks_exact(2)
+                        .map(|point| (point[0] as f32, point[1] as f32))
+                        .collect();
+                    let path = if addr == "/path" {
+                        Path::polyline(points)
+                    } else {
+                        Path::bezier(&points)
+                    };
+                    path.map(|path| {
+                        let follower = PathFollower::new(path, speed as i16, lookahead as f32);
+                        Action::Steer(Some(Controller::Path(follower)))
+                    })
+                }
+                "/goto" => {
+                    let defaults = GoToOptions::default();
+                    let options = GoToOptions {
+                        max_speed: floats.get(3).map_or(defaults.max_speed, |v| *v as i16),
+                        tolerance: *floats.get(4).unwrap_or(&defaults.tolerance),
+                        distance_gain: *floats.get(5).unwrap_or(&defaults.distance_gain),
+                        angle_gain: *floats.get(6).unwrap_or(&defaults.angle_gain),
+                        ..defaults
+                    };
+                    let goto = GoTo::new(*floats.get(1)?, *floats.get(2)?, options);
+                    Some(Action::Steer(Some(Controller::GoTo(goto))))
+                }
+                "/path/stop" | "/goto/stop" => Some(Action::Steer(None)),
+                "/odometry" => Some(Action::Query(Query::Odometry)),
+                "/state" => Some(Action::Query(Query::State)),
+                _ => cmd.map(Action::Command),
+            };
+
+            // Return triple of (toioID, action, sequence number)
+            let toionum = *vals.first()? as usize;
+            return action.map(|action| (toionum, action, seq));
+        }
+        _ => None,
+    }
+}
+
+/// Converts the positions given as arguments of a message from the given units
+/// into mat coordinates, so that the message can be handled as if they were
+/// given in mat coordinates. Absolute angles are turned from the world frame
+/// to the mat the position is on. Returns false if a position is not on a mat
+/// in the layout, or if the positions are not all on the same mat.
+pub fn convert_units(msg: &mut OscMessage, layout: &MatLayout, units: Units) -> bool {
+    if units == Units::Raw {
+        return true;
+    }
+
+    // index of the first position, how far apart positions are and where the
+    // angle is after each position
+    let (addr, offset) = match msg.addr.strip_prefix("/seq") {
+        Some(addr) => (addr.to_string(), 1),
+        None => (msg.addr.clone(), 0),
+    };
+    let (first, stride, angle) = match addr.as_str() {
+        "/motortarget" | "/motortarget/absolute" | "/motortarget/relative" => {
+            (6 + offset, 0, Some(2))
         }
+        "/multitarget"
+        | "/multitarget/absolute"
+        | "/multitarget/relative"
+        | "/multitarget/overwrite"
+        | "/multitarget/overwrite/absolute"
+        | "/multitarget/overwrite/relative" => (6 + offset, 3, Some(2)),
+        "/goto" => (1 + offset, 0, None),
+        "/path" | "/path/bezier" => (3 + offset, 2, None),
+        _ => return true,
+    };
+
+    let mut mat = None;
+    let mut i = first;
+    while i + 1 < msg.args.len() {
+        let (Some(x), Some(y)) = (osc_float(&msg.args[i]), osc_float(&msg.args[i + 1])) else {
+            return false;
+        };
+        let Some(index) = layout.find(x, y, units) else {
+            return false;
+        };
+        if *mat.get_or_insert(index) != index {
+            return false;
+        }
+        let Some((x, y)) = layout.from_units(x, y, units) else {
+            return false;
+        };
+        msg.args[i] = OscType::Int(x as i32);
+        msg.args[i + 1] = OscType::Int(y as i32);
+
+        let placement = &layout.placements[index];
+        if let Some(arg) = angle.and_then(|angle| msg.args.get_mut(i + angle)) {
+            if let Some(theta) = mat_angle(&addr, arg, placement) {
+                *arg = theta;
+            }
+        }
+
+        if stride == 0 {
+            break;
+        }
+        i += stride;
+    }
+    return true;
+}
+
+/// Turns an absolute target angle from the world frame to the given mat, in
+/// the same form as the angle argument of the address
+fn mat_angle(addr: &str, arg: &OscType, placement: &MatPlacement) -> Option<OscType> {
+    let theta = osc_float(arg)?;
+    if addr.ends_with("/relative") {
+        return None;
+    }
+    if addr.ends_with("/absolute") {
+        return Some(OscType::Int(
+            placement.from_world_angle(theta).round() as i32
+        ));
+    }
+
+    let turn = |degrees: u16| -> u16 {
+        return placement.from_world_angle(degrees as f32).round() as u16 % 360;
+    };
+    let angle = match unpack_angle(theta as u16)? {
+        TargetAngle::Absolute(degrees) => TargetAngle::Absolute(turn(degrees)),
+        TargetAngle::AbsolutePositive(degrees) => TargetAngle::AbsolutePositive(turn(degrees)),
+        TargetAngle::AbsoluteNegative(degrees) => TargetAngle::AbsoluteNegative(turn(degrees)),
+        _ => return None,
+    };
+    return Some(OscType::Int(pack_angle(angle) as i32));
+}
+
+fn osc_float(val: &OscType) -> Option<f32> {
+    return match val {
+        OscType::Int(i) => Some(*i as f32),
+        OscType::Float(f) => Some(*f),
         _ => None,
+    };
+}
+
+/// Converts the angle argument of a target command. Addresses ending in
+/// `/absolute` or `/relative` take the angle in degrees, and otherwise the
+/// angle is packed with its angle mode as described in the toio spec.
+fn target_angle(addr: &str, val: i32) -> Option<TargetAngle> {
+    if addr.ends_with("/absolute") {
+        return Some(TargetAngle::Absolute(val.rem_euclid(360) as u16));
     }
+    if addr.ends_with("/relative") {
+        return Some(TargetAngle::Relative(val.clamp(-0x1FFF, 0x1FFF) as i16));
+    }
+    return unpack_angle(val as u16);
 }
 
-pub fn send_packet(socket: &UdpSocket, to_addr: &str, id: usize, update: Update) {
+/// Converts an update from a toio into an OSC packet, if it has an OSC address
+pub fn encode_update(id: usize, update: Update) -> Option<OscPacket> {
     let vals: Option<(&str, Vec<i32>)> = match update {
         Update::Position {
             x_center,
@@ -174,6 +511,9 @@ pub fn send_packet(socket: &UdpSocket, to_addr: &str, id: usize, update: Update)
             left_speed,
             right_speed,
         } => Some(("/motorSpeed", vec![left_speed as i32, right_speed as i32])),
+        Update::MotorSpeedConfigResponse { response } => {
+            Some(("/motorSpeedConfig", vec![response as i32]))
+        }
         Update::PostureEuler { roll, pitch, yaw } => {
             Some(("/postureEuler", vec![roll as i32, pitch as i32, yaw as i32]))
         }
@@ -201,17 +541,320 @@ pub fn send_packet(socket: &UdpSocket, to_addr: &str, id: usize, update: Update)
         _ => None,
     };
 
-    if let Some((addr, args)) = vals {
-        let msg = encoder::encode(&OscPacket::Message(OscMessage {
+    return vals.map(|(addr, args)| encode_message(addr, id, args));
+}
+
+/// Converts a position in millimetres or normalized units into an OSC packet,
+/// where the position is given as floats
+pub fn encode_position(id: usize, x: f32, y: f32, theta: u16) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: "/position".to_string(),
+        args: vec![
+            OscType::Int(id as i32),
+            OscType::Float(x),
+            OscType::Float(y),
+            OscType::Int(theta as i32),
+        ],
+    });
+}
+
+/// Reports a gesture made with the button of a toio as `/button/<name> id`
+pub fn encode_button(id: usize, event: ButtonEvent) -> OscPacket {
+    return encode_message(&format!("/button/{}", event.name()), id, vec![]);
+}
+
+/// Reports something that happened to a toio as `/event/<name> id`, where a
+/// flip also gives the new posture
+pub fn encode_event(id: usize, event: CubeEvent) -> OscPacket {
+    let args = match event {
+        CubeEvent::Flipped { posture } => vec![posture as i32],
+        _ => vec![],
+    };
+    return encode_message(&format!("/event/{}", event.name()), id, args);
+}
+
+/// Reports the estimated pose of a toio as floats in the units picked with
+/// `/units`, degrees and per second, as `/pose id x y theta vx vy omega`
+pub fn encode_pose(id: usize, pose: Pose) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: "/pose".to_string(),
+        args: std::iter::once(OscType::Int(id as i32))
+            .chain(
+                [pose.x, pose.y, pose.theta, pose.vx, pose.vy, pose.omega]
+                    .into_iter()
+                    .map(OscType::Float),
+            )
+            .collect(),
+    });
+}
+
+/// Reports how far a toio has driven as `/odometry id distance heading moving
+/// displacement`, where the distance travelled either way and the distance
+/// forwards less the distance backwards are in mat units, the angle turned
+/// clockwise is in degrees and the time moving is in seconds. Which way the
+/// wheels turn is taken from the motor commands sent by the bridge, so wheels
+/// driven by the toio itself or by another app are counted as turning forwards.
+pub fn encode_odometry(id: usize, odometry: &Odometry) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: "/odometry".to_string(),
+        args: vec![
+            OscType::Int(id as i32),
+            OscType::Float(odometry.distance),
+            OscType::Float(odometry.heading),
+            OscType::Float(odometry.moving.as_secs_f32()),
+            OscType::Float(odometry.displacement),
+        ],
+    });
+}
+
+/// Reports the latest of everything a toio has reported as `/state id x y theta
+/// on_mat standard battery button horizontal collision double_tap posture shake
+/// roll pitch yaw magnetic_state magnetic_strength force_x force_y force_z
+/// left_speed right_speed`, where the standard ID and battery are -1 when they
+/// are not known and flags are 0 or 1. The position is in mat coordinates, or
+/// is given as floats when it was converted into other units like `/position`.
+pub fn encode_state(id: usize, state: &CubeState, position: Option<(f32, f32, u16)>) -> OscPacket {
+    let (force_x, force_y, force_z) = state.magnetic_force;
+    let mut packet = encode_message(
+        "/state",
+        id,
+        vec![
+            state.x as i32,
+            state.y as i32,
+            state.theta as i32,
+            state.on_mat as i32,
+            state.standard.map_or(-1, |standard| standard as i32),
+            state.battery.map_or(-1, |level| level as i32),
+            state.button as i32,
+            state.horizontal as i32,
+            state.collision as i32,
+            state.double_tap as i32,
+            state.posture as i32,
+            state.shake as i32,
+            state.roll as i32,
+            state.pitch as i32,
+            state.yaw as i32,
+            state.magnetic_state as i32,
+            state.magnetic_strength as i32,
+            force_x as i32,
+            force_y as i32,
+            force_z as i32,
+            state.left_speed as i32,
+            state.right_speed as i32,
+        ],
+    );
+    if let (OscPacket::Message(msg), Some((x, y, theta))) = (&mut packet, position) {
+        msg.args[1] = OscType::Float(x);
+        msg.args[2] = OscType::Float(y);
+        msg.args[3] = OscType::Int(theta as i32);
+    }
+    return packet;
+}
+
+/// Reports the name of a card or sticker a toio was placed on
+pub fn encode_card(id: usize, name: &str) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: "/card".to_string(),
+        args: vec![OscType::Int(id as i32), OscType::String(name.to_string())],
+    });
+}
+
+/// Reports the outcome of a command sent with a sequence number. A status of 0
+/// means the command was delivered, -1 means the write to the toio failed, and
+/// for target commands any other value is the response code from the toio.
+pub fn encode_ack(id: usize, seq: u32, status: i32) -> OscPacket {
+    return encode_message("/ack", id, vec![seq as i32, status]);
+}
+
+/// Reports how far along its path a toio is, as a percentage
+pub fn encode_path_progress(id: usize, percent: i32) -> OscPacket {
+    return encode_message("/path/progress", id, vec![percent]);
+}
+
+/// Reports that a toio has reached the end of its path
+pub fn encode_path_done(id: usize) -> OscPacket {
+    return encode_message("/path/done", id, vec![]);
+}
+
+/// Reports that a toio has stopped going to a point, where a status of 0 means
+/// it arrived and 2 means it was off the mat for too long, as for the response
+/// to a target command
+pub fn encode_goto_done(id: usize, status: i32) -> OscPacket {
+    return encode_message("/goto/done", id, vec![status]);
+}
+
+/// Reports which goal of a formation a toio was given
+pub fn encode_formation_assign(id: usize, goal: usize) -> OscPacket {
+    return encode_message("/formation/assign", id, vec![goal as i32]);
+}
+
+/// Reports that every toio in a formation has responded, with how many
+/// arrived and how many were given a goal
+pub fn encode_formation_done(arrived: usize, assigned: usize) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: "/formation/done".to_string(),
+        args: vec![OscType::Int(arrived as i32), OscType::Int(assigned as i32)],
+    });
+}
+
+fn encode_message(addr: &str, id: usize, args: Vec<i32>) -> OscPacket {
+    return OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args: std::iter::once(id as i32)
+            .chain(args)
+            .map(OscType::Int)
+            .collect(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(addr: &str, args: &[i32]) -> OscPacket {
+        return OscPacket::Message(OscMessage {
             addr: addr.to_string(),
-            args: vec![id as i32]
-                .iter()
-                .chain(args.iter())
-                .map(|x| OscType::Int(*x))
-                .collect(),
-        }))
-        .unwrap();
+            args: args.iter().map(|arg| OscType::Int(*arg)).collect(),
+        });
+    }
+
+    #[test]
+    fn ignores_messages_too_short_once_the_seq_is_removed() {
+        assert_eq!(
+            handle_packet(message("/seq/led", &[0, 5, 100, 255, 0, 0])),
+            Some((
+                0,
+                Action::Command(Command::Led {
+                    duration: 100,
+                    red: 255,
+                    green: 0,
+                    blue: 0
+                }),
+                Some(5)
+            ))
+        );
+        assert_eq!(handle_packet(message("/seq/led", &[0, 5])), None);
+        assert_eq!(handle_packet(message("/seq/motorbasic", &[0, 5, 1])), None);
+        assert_eq!(handle_packet(message("/seq/sound", &[0, 5, 1])), None);
+        assert_eq!(handle_packet(message("/seq/midi", &[0, 5])), None);
+        assert_eq!(handle_packet(message("/midi", &[0, 1, 10, 60])), None);
+        assert_eq!(handle_packet(message("/multiLed", &[0, 1, 10])), None);
+        assert_eq!(handle_packet(message("/path/stop", &[])), None);
+        assert_eq!(handle_packet(message("/config/motorspeed", &[0])), None);
+    }
+
+    #[test]
+    fn rejects_malformed_targets() {
+        let target = [0, 1, 0, 0, 80, 0, 100, 200, 90];
+        assert_eq!(
+            handle_packet(message("/motortarget/absolute", &target)),
+            Some((
+                0,
+                Action::Command(Command::MotorTarget {
+                    control: 1,
+                    timeout: 0,
+                    move_type: 0,
+                    max_speed: 80,
+                    speed_change: 0,
+                    x_target: 100,
+                    y_target: 200,
+                    theta_target: TargetAngle::Absolute(90),
+                }),
+                None
+            ))
+        );
+        assert_eq!(
+            handle_packet(message("/motortarget/absolute", &target[..8])),
+            None
+        );
+
+        let multi = [0, 1, 0, 0, 80, 0, 100, 200, 90, 150, 250, 0];
+        match handle_packet(message("/multitarget/absolute", &multi)) {
+            Some((0, Action::Command(Command::MultiTarget { targets, .. }), None)) => {
+                assert_eq!(targets.len(), 2)
+            }
+            action => panic!("expected a multi target, got {:?}", action),
+        }
+        for end in [3, 6, 8, 11] {
+            assert_eq!(
+                handle_packet(message("/multitarget/absolute", &multi[..end])),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_long_overwrite_paths_missing_a_value() {
+        let mut path = vec![0, 1, 0, 0, 80, 0];
+        for i in 0..200 {
+            path.extend_from_slice(&[100 + i, 200, 0]);
+        }
+        match handle_packet(message("/multitarget/overwrite/absolute", &path)) {
+            Some((
+                0,
+                Action::Command(Command::MultiTarget {
+                    targets, op_add, ..
+                }),
+                None,
+            )) => {
+                assert_eq!(targets.len(), 200);
+                assert_eq!(op_add, WriteMode::Overwrite);
+            }
+            action => panic!("expected a multi target, got {:?}", action),
+        }
+
+        path.pop();
+        assert_eq!(
+            handle_packet(message("/multitarget/overwrite/absolute", &path)),
+            None
+        );
+        assert_eq!(
+            handle_packet(message("/multitarget/overwrite", &path)),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_short_wheels_messages() {
+        assert_eq!(handle_packet(message("/wheels", &[0])), None);
+        assert_eq!(handle_packet(message("/wheels", &[0, 50])), None);
+        assert_eq!(
+            handle_packet(message("/wheels", &[0, 50, -50])),
+            Some((0, Action::Command(Command::wheels(50, -50)), None))
+        );
+    }
+
+    #[test]
+    fn sends_the_state_in_the_units_in_use() {
+        let state = CubeState {
+            x: 300,
+            y: 200,
+            theta: 90,
+            ..CubeState::new()
+        };
+        let OscPacket::Message(raw) = encode_state(1, &state, None) else {
+            panic!();
+        };
+        assert_eq!(raw.args[1..4], [300, 200, 90].map(OscType::Int));
+        let OscPacket::Message(mm) = encode_state(1, &state, Some((10.5, 20.0, 180))) else {
+            panic!();
+        };
+        assert_eq!(
+            mm.args[1..4],
+            [
+                OscType::Float(10.5),
+                OscType::Float(20.0),
+                OscType::Int(180)
+            ]
+        );
+        assert_eq!(mm.args[4..], raw.args[4..]);
 
-        socket.send_to(&msg, to_addr).unwrap();
+        // formation goals are placed in the world frame when forming, not here
+        let layout = MatLayout::grid(&[MatType::Simple; 2], 2).unwrap();
+        let OscPacket::Message(mut msg) = message("/formation", &[500, 100, 0]) else {
+            panic!();
+        };
+        assert!(convert_units(&mut msg, &layout, Units::Millimetres));
+        assert_eq!(msg.args, [500, 100, 0].map(OscType::Int));
     }
 }
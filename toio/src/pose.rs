@@ -0,0 +1,209 @@
+use std::time::{Duration, Instant};
+
+use crate::motion::UNITS_PER_SPEED;
+
+/// How much of the difference between a predicted and a measured position is
+/// corrected straight away
+const ALPHA: f32 = 0.5;
+
+/// How much of the difference between a predicted and a measured position is
+/// corrected in the velocity
+const BETA: f32 = 0.2;
+
+/// How long the speed of the wheels is used for after it is notified
+const WHEELS_STALE: Duration = Duration::from_millis(300);
+
+/// Estimated position, angle and velocity of a toio, in mat units, degrees and
+/// per second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+    pub vx: f32,
+    pub vy: f32,
+    pub omega: f32,
+}
+
+/// Estimates the pose of a toio between position updates with an alpha-beta
+/// filter, which predicts from the last velocity and corrects the prediction
+/// by part of the difference to each measured position. The speed of the
+/// wheels and the yaw of the posture fill in the velocity, and the pose keeps
+/// being predicted for a short time after the toio leaves the mat.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseEstimator {
+    predict_for: Duration,
+    pose: Option<Pose>,
+    at: Instant,
+    seen: Instant,
+    on_mat: bool,
+    wheels: Option<(f32, Instant)>,
+    yaw: Option<f32>,
+}
+
+impl Default for PoseEstimator {
+    fn default() -> PoseEstimator {
+        return PoseEstimator::new(Duration::from_millis(500));
+    }
+}
+
+impl PoseEstimator {
+    /// Estimates a pose, which keeps being predicted for the given time after
+    /// the toio leaves the mat
+    pub fn new(predict_for: Duration) -> PoseEstimator {
+        let now = Instant::now();
+        return PoseEstimator {
+            predict_for,
+            pose: None,
+            at: now,
+            seen: now,
+            on_mat: false,
+            wheels: None,
+            yaw: None,
+        };
+    }
+
+    /// Corrects the pose with a measured position in mat units and angle in
+    /// degrees. A toio that was off the mat starts again from the position.
+    pub fn position(&mut self, x: f32, y: f32, theta: f32, now: Instant) {
+        let dt = now.saturating_duration_since(self.at).as_secs_f32();
+        let mut pose = match self.pose.filter(|_| self.on_mat && dt > 0.0) {
+            Some(pose) => {
+                let predicted = advance(pose, dt);
+                let residual = (
+                    x - predicted.x,
+                    y - predicted.y,
+                    wrap(theta - predicted.theta),
+                );
+                Pose {
+                    x: predicted.x + ALPHA * residual.0,
+                    y: predicted.y + ALPHA * residual.1,
+                    theta: (predicted.theta + ALPHA * residual.2).rem_euclid(360.0),
+                    vx: pose.vx + BETA * residual.0 / dt,
+                    vy: pose.vy + BETA * residual.1 / dt,
+                    omega: pose.omega + BETA * residual.2 / dt,
+                }
+            }
+            None => Pose {
+                x,
+                y,
+                theta,
+                ..Pose::default()
+            },
+        };
+
+        // the wheels only give the speed, so keep the direction of the filter
+        if let Some((speed, at)) = self.wheels {
+            if now.saturating_duration_since(at) < WHEELS_STALE {
+                let (sin, cos) = pose.theta.to_radians().sin_cos();
+                let sign = if pose.vx * cos + pose.vy * sin < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                pose.vx = (pose.vx + sign * speed * cos) / 2.0;
+                pose.vy = (pose.vy + sign * speed * sin) / 2.0;
+                if speed == 0.0 {
+                    pose.omega = 0.0;
+                }
+            }
+        }
+
+        self.pose = Some(pose);
+        self.at = now;
+        self.seen = now;
+        self.on_mat = true;
+    }
+
+    /// Records the speed of each wheel from a motor speed notification
+    pub fn motor_speed(&mut self, left: u8, right: u8, now: Instant) {
+        let speed = (left as f32 + right as f32) / 2.0 * UNITS_PER_SPEED;
+        self.wheels = Some((speed, now));
+    }
+
+    /// Records the yaw of the posture in degrees, which turns the predicted
+    /// pose while the toio is off the mat. Yaw is counter-clockwise seen from
+    /// above, while the angle on the mat is clockwise.
+    pub fn posture(&mut self, yaw: f32, now: Instant) {
+        if let (Some(last), Some(pose), false) = (self.yaw, self.pose, self.on_mat) {
+            let mut pose = advance(pose, now.saturating_duration_since(self.at).as_secs_f32());
+            pose.theta = (pose.theta - wrap(yaw - last)).rem_euclid(360.0);
+            pose.omega = 0.0;
+            self.pose = Some(pose);
+            self.at = now;
+        }
+        self.yaw = Some(yaw);
+    }
+
+    /// Records that the toio has left the mat
+    pub fn missed(&mut self, now: Instant) {
+        if !self.on_mat {
+            return;
+        }
+        if let Some(pose) = self.pose {
+            self.pose = Some(advance(
+                pose,
+                now.saturating_duration_since(self.at).as_secs_f32(),
+            ));
+        }
+        self.at = now;
+        self.on_mat = false;
+    }
+
+    /// Predicts the pose at the given time, unless the toio has not been seen
+    /// or has been off the mat for too long
+    pub fn predict(&self, now: Instant) -> Option<Pose> {
+        if !self.on_mat && now.saturating_duration_since(self.seen) > self.predict_for {
+            return None;
+        }
+        return self
+            .pose
+            .map(|pose| advance(pose, now.saturating_duration_since(self.at).as_secs_f32()));
+    }
+}
+
+/// Moves a pose on by its velocity for the given number of seconds
+fn advance(pose: Pose, dt: f32) -> Pose {
+    return Pose {
+        x: pose.x + pose.vx * dt,
+        y: pose.y + pose.vy * dt,
+        theta: (pose.theta + pose.omega * dt).rem_euclid(360.0),
+        ..pose
+    };
+}
+
+/// Wraps an angle in degrees to between -180 and 180
+fn wrap(degrees: f32) -> f32 {
+    return (degrees + 180.0).rem_euclid(360.0) - 180.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predicts_between_and_after_positions() {
+        let start = Instant::now();
+        let mut estimator = PoseEstimator::new(Duration::from_millis(500));
+        assert_eq!(estimator.predict(start), None);
+
+        // drive right at 100 units per second, turning at 20 degrees per second
+        for i in 0..40 {
+            let t = i as f32 * 0.05;
+            let now = start + Duration::from_secs_f32(t);
+            estimator.position(100.0 + 100.0 * t, 200.0, 20.0 * t, now);
+        }
+        let end = start + Duration::from_secs_f32(39.0 * 0.05);
+
+        let pose = estimator.predict(end + Duration::from_millis(100)).unwrap();
+        assert!((pose.vx - 100.0).abs() < 5.0 && pose.vy.abs() < 5.0);
+        assert!((pose.omega - 20.0).abs() < 2.0);
+        assert!((pose.x - 305.0).abs() < 3.0);
+
+        estimator.missed(end + Duration::from_millis(50));
+        assert!(estimator
+            .predict(end + Duration::from_millis(400))
+            .is_some());
+        assert_eq!(estimator.predict(end + Duration::from_millis(600)), None);
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes at the start of every recording
+const MAGIC: &[u8; 8] = b"toiorec1";
+
+/// Longest packet in a recording, which is as large as any UDP packet
+pub const MAX_PACKET: usize = 65536;
+
+/// Whether a recorded packet was received by the bridge or sent by it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// OSC packet recorded with the time since the recording started
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub at: Duration,
+    pub direction: Direction,
+    pub packet: Vec<u8>,
+}
+
+/// Writes every OSC packet the bridge receives and sends to a file. Each
+/// record is the time since the recording started in microseconds as a u64,
+/// a direction byte that is 0 for incoming and 1 for outgoing, the length of
+/// the packet as a u32 and the packet itself, with numbers in little endian.
+///
+/// Packets are passed to a thread that writes them, so recording never blocks
+/// the bridge. The file is flushed whenever the thread has written every
+/// packet it was given.
+#[derive(Clone)]
+pub struct Recorder {
+    records: Sender<Record>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates a recording, replacing any file at the path
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.flush()?;
+
+        let (records, received) = mpsc::channel::<Record>();
+        thread::spawn(move || {
+            while let Ok(mut record) = received.recv() {
+                loop {
+                    if let Err(err) = write_record(&mut writer, &record) {
+                        eprintln!("Error writing recording: {}", err);
+                    }
+                    record = match received.try_recv() {
+                        Ok(record) => record,
+                        Err(_) => break,
+                    };
+                }
+                if let Err(err) = writer.flush() {
+                    eprintln!("Error writing recording: {}", err);
+                }
+            }
+        });
+
+        return Ok(Recorder {
+            records,
+            start: Instant::now(),
+        });
+    }
+
+    /// Adds a packet to the recording
+    pub fn record(&self, direction: Direction, packet: &[u8]) {
+        let _ = self.records.send(Record {
+            at: self.start.elapsed(),
+            direction,
+            packet: packet.to_vec(),
+        });
+    }
+}
+
+/// Writes a record in the format described for `Recorder`
+pub fn write_record(writer: &mut impl Write, record: &Record) -> io::Result<()> {
+    writer.write_all(&(record.at.as_micros() as u64).to_le_bytes())?;
+    writer.write_all(&[match record.direction {
+        Direction::Incoming => 0,
+        Direction::Outgoing => 1,
+    }])?;
+    writer.write_all(&(record.packet.len() as u32).to_le_bytes())?;
+    writer.write_all(&record.packet)?;
+    return Ok(());
+}
+
+/// Reads every record from a recording
+pub fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+    return read_from(&mut BufReader::new(File::open(path)?));
+}
+
+/// Reads every record from a recording in the format described for
+/// `Recorder`. A record cut short at the end, such as when the bridge was
+/// stopped while writing it, is left out.
+pub fn read_from(reader: &mut impl Read) -> io::Result<Vec<Record>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a toio recording"));
+    }
+
+    let mut records = vec![];
+    loop {
+        let mut header = [0u8; 13];
+        let mut packet = vec![];
+        let result = reader.read_exact(&mut header).and_then(|_| {
+            let len = u32::from_le_bytes([header[9], header[10], header[11], header[12]]);
+            if len as usize > MAX_PACKET {
+                return Err(invalid("recorded packet is too long"));
+            }
+            packet = vec![0u8; len as usize];
+            return reader.read_exact(&mut packet);
+        });
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+
+        let mut at = [0u8; 8];
+        at.copy_from_slice(&header[..8]);
+        records.push(Record {
+            at: Duration::from_micros(u64::from_le_bytes(at)),
+            direction: match header[8] {
+                0 => Direction::Incoming,
+                1 => Direction::Outgoing,
+                _ => return Err(invalid("unknown direction of recorded packet")),
+            },
+            packet,
+        });
+    }
+    return Ok(records);
+}
+
+/// Parses a cube remapping such as `0:2`, which replays the commands recorded
+/// for cube 0 to cube 2
+pub fn parse_remap(remap: &str) -> Result<(usize, usize), String> {
+    let (from, to) = remap
+        .split_once(':')
+        .ok_or_else(|| format!("expected from:to, got {}", remap))?;
+    let parse = |id: &str| -> Result<usize, String> {
+        return id.parse().map_err(|_| format!("invalid cube ID {}", id));
+    };
+    return Ok((parse(from)?, parse(to)?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(records: &[Record]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for record in records {
+            write_record(&mut bytes, record).unwrap();
+        }
+        return bytes;
+    }
+
+    fn records() -> Vec<Record> {
+        return vec![
+            Record {
+                at: Duration::from_micros(1500),
+                direction: Direction::Incoming,
+                packet: b"/led\0\0\0\0".to_vec(),
+            },
+            Record {
+                at: Duration::from_secs(2),
+                direction: Direction::Outgoing,
+                packet: vec![],
+            },
+        ];
+    }
+
+    #[test]
+    fn reads_back_written_records() {
+        let bytes = recording(&records());
+        assert_eq!(read_from(&mut &bytes[..]).unwrap(), records());
+        assert_eq!(read_from(&mut &MAGIC[..]).unwrap(), vec![]);
+        assert!(read_from(&mut &b"toiorec2"[..]).is_err());
+    }
+
+    #[test]
+    fn reads_truncated_and_corrupt_recordings() {
+        // a record cut short is left out, wherever it is cut
+        let bytes = recording(&records());
+        let second = MAGIC.len() + 13 + 8;
+        for end in [second + 1, second + 12] {
+            assert_eq!(read_from(&mut &bytes[..end]).unwrap(), records()[..1]);
+        }
+        assert!(read_from(&mut &bytes[..4]).is_err());
+
+        // lengths longer than any packet are not read
+        let mut long = MAGIC.to_vec();
+        long.extend_from_slice(&[0; 9]);
+        long.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_from(&mut &long[..]).is_err());
+
+        let mut direction = recording(&records()[..1]);
+        direction[MAGIC.len() + 8] = 7;
+        assert!(read_from(&mut &direction[..]).is_err());
+    }
+
+    #[test]
+    fn parses_remappings() {
+        assert_eq!(parse_remap("0:2"), Ok((0, 2)));
+        assert_eq!(parse_remap("10:3"), Ok((10, 3)));
+        assert!(parse_remap("0").is_err());
+        assert!(parse_remap("a:1").is_err());
+        assert!(parse_remap("-1:2").is_err());
+    }
+}
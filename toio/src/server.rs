@@ -0,0 +1,903 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use rosc::{encoder, OscPacket, OscType};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until};
+use tokio_util::sync::CancellationToken;
+
+use crate::osc::*;
+use crate::record::*;
+use crate::slip::{self, SlipDecoder};
+use toio::codec::split_multi_target;
+use toio::*;
+
+/// List of every toio that has connected, indexed by the ID used over OSC
+pub type Connected = Arc<RwLock<Vec<Arc<RwLock<Toio>>>>>;
+
+/// How long to wait for every toio in a formation before reporting it as done,
+/// which is a little longer than the toio's own timeout for target commands
+const FORMATION_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a replay waits for every toio in the recording to connect
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors the server has had, which are counted rather than printed so that
+/// they do not write over the TUI
+#[derive(Debug, Default)]
+pub struct ServerErrors {
+    /// Packets that could not be sent
+    pub send: AtomicUsize,
+    /// Packets that could not be received, including SLIP frames that were
+    /// too long
+    pub receive: AtomicUsize,
+    /// Packets that were received but could not be handled, such as unknown
+    /// commands or positions that are not on a mat
+    pub rejected: AtomicUsize,
+}
+
+impl fmt::Display for ServerErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(
+            f,
+            "{} send, {} receive, {} rejected",
+            self.send.load(Ordering::Relaxed),
+            self.receive.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Async OSC server that listens for commands on one or more UDP sockets
+/// and sends updates from the toios to a remote address. It can also accept
+/// TCP connections using SLIP framing, which send commands and receive updates
+/// on the same stream.
+#[derive(Clone)]
+pub struct OscServer {
+    sockets: Vec<Arc<UdpSocket>>,
+    listeners: Vec<Arc<TcpListener>>,
+    streams: broadcast::Sender<Vec<u8>>,
+    to_addr: SocketAddr,
+    layout: Arc<RwLock<MatLayout>>,
+    units: Arc<RwLock<Units>>,
+    recorder: Option<Recorder>,
+    formation: Arc<RwLock<Option<Formation>>>,
+    errors: Arc<ServerErrors>,
+    shutdown: CancellationToken,
+}
+
+impl OscServer {
+    /// Binds a UDP socket to every address given, and a TCP listener on the
+    /// same addresses if a TCP port is given. IPv6 sockets are bound as
+    /// IPv6-only so that they can share a port with an IPv4 socket.
+    pub fn bind(
+        addrs: &[SocketAddr],
+        tcp_port: Option<u16>,
+        to_addr: SocketAddr,
+    ) -> io::Result<OscServer> {
+        let mut sockets = vec![];
+        let mut listeners = vec![];
+
+        for addr in addrs.iter() {
+            let socket = bind_socket(*addr, Type::DGRAM, Protocol::UDP)?;
+            sockets.push(Arc::new(UdpSocket::from_std(socket.into())?));
+
+            if let Some(port) = tcp_port {
+                let tcp_addr = SocketAddr::new(addr.ip(), port);
+                let socket = bind_socket(tcp_addr, Type::STREAM, Protocol::TCP)?;
+                socket.listen(128)?;
+                listeners.push(Arc::new(TcpListener::from_std(socket.into())?));
+            }
+        }
+
+        let (streams, _) = broadcast::channel(256);
+
+        return Ok(OscServer {
+            sockets,
+            listeners,
+            streams,
+            to_addr,
+            layout: Arc::new(RwLock::new(MatLayout::default())),
+            units: Arc::new(RwLock::new(Units::default())),
+            recorder: None,
+            formation: Arc::new(RwLock::new(None)),
+            errors: Arc::new(ServerErrors::default()),
+            shutdown: CancellationToken::new(),
+        });
+    }
+
+    /// Sets the mats the toios are on and the units positions are given in
+    pub fn with_mats(self, layout: MatLayout, units: Units) -> OscServer {
+        return OscServer {
+            layout: Arc::new(RwLock::new(layout)),
+            units: Arc::new(RwLock::new(units)),
+            ..self
+        };
+    }
+
+    /// Records every packet the server receives and sends
+    pub fn with_recorder(self, recorder: Recorder) -> OscServer {
+        return OscServer {
+            recorder: Some(recorder),
+            ..self
+        };
+    }
+
+    /// Starts a task for each socket and listener that forwards incoming
+    /// commands to the connected toios until the server is shut down
+    pub fn listen(&self, connected: Connected, swarm: Arc<RwLock<Swarm>>) -> Vec<JoinHandle<()>> {
+        let udp = self.sockets.iter().map(|socket| {
+            let server = self.clone();
+            let socket = socket.clone();
+            let connected = connected.clone();
+            let swarm = swarm.clone();
+
+            tokio::spawn(async move {
+                // large enough for any UDP packet, such as a long multi target path
+                let mut buf = vec![0u8; 65536];
+
+                loop {
+                    let size = tokio::select! {
+                        _ = server.shutdown.cancelled() => break,
+                        result = socket.recv(&mut buf) => match result {
+                            Ok(size) => size,
+                            Err(_) => {
+                                server.errors.receive.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                        },
+                    };
+
+                    server.handle(&connected, &swarm, &buf[..size]).await;
+                }
+            })
+        });
+
+        let tcp = self.listeners.iter().map(|listener| {
+            let server = self.clone();
+            let listener = listener.clone();
+            let connected = connected.clone();
+            let swarm = swarm.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let stream = tokio::select! {
+                        _ = server.shutdown.cancelled() => break,
+                        result = listener.accept() => match result {
+                            Ok((stream, _)) => stream,
+                            Err(err) => {
+                                eprintln!("Error accepting OSC connection: {}", err);
+                                continue;
+                            }
+                        },
+                    };
+
+                    let serve = server
+                        .clone()
+                        .serve(stream, connected.clone(), swarm.clone());
+                    tokio::spawn(serve);
+                }
+            })
+        });
+
+        return udp.chain(tcp).collect();
+    }
+
+    /// Reads SLIP framed commands from a TCP stream, and writes every packet
+    /// sent by the server back to it, until either side closes
+    async fn serve(self, stream: TcpStream, connected: Connected, swarm: Arc<RwLock<Swarm>>) {
+        let (mut reader, mut writer) = stream.into_split();
+        let mut frames = self.streams.subscribe();
+        let mut decoder = SlipDecoder::new();
+        let mut buf = [0u8; 4096];
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                result = reader.read(&mut buf) => match result {
+                    Ok(0) => break,
+                    Ok(size) => {
+                        let dropped = decoder.dropped();
+                        for packet in decoder.decode(&buf[..size]) {
+                            self.handle(&connected, &swarm, &packet).await;
+                        }
+                        let dropped = decoder.dropped() - dropped;
+                        self.errors.receive.fetch_add(dropped, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        eprintln!("Error reading OSC stream: {}", err);
+                        break;
+                    }
+                },
+                frame = frames.recv() => match frame {
+                    Ok(frame) => {
+                        if let Err(err) = writer.write_all(&frame).await {
+                            eprintln!("Error writing OSC stream: {}", err);
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+
+    /// Records an OSC packet that was received, then handles it
+    async fn handle(&self, connected: &Connected, swarm: &Arc<RwLock<Swarm>>, buf: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Incoming, buf);
+        }
+        self.process(connected, swarm, buf).await;
+    }
+
+    /// Decodes an OSC packet and sends its command to the toio
+    async fn process(&self, connected: &Connected, swarm: &Arc<RwLock<Swarm>>, buf: &[u8]) {
+        if let Ok((_, mut packet)) = rosc::decoder::decode_udp(buf) {
+            // convert targets given in other units into mat coordinates
+            if let OscPacket::Message(msg) = &mut packet {
+                let units = *self.units.read().await;
+                if !convert_units(msg, &*self.layout.read().await, units) {
+                    self.errors.rejected.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+
+            if let Some(action) = handle_session_packet(&packet) {
+                match action {
+                    SessionAction::Avoid { enabled, radius } => {
+                        let mut swarm = swarm.write().await;
+                        swarm.enabled = enabled;
+                        swarm.radius = radius.unwrap_or(swarm.radius);
+                    }
+                    SessionAction::Formation(goals) => {
+                        self.form(connected, swarm, goals).await;
+                    }
+                    SessionAction::Mats { mats, columns } => {
+                        match MatLayout::grid(&mats, columns) {
+                            Ok(layout) => *self.layout.write().await = layout,
+                            Err(_) => {
+                                self.errors.rejected.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    SessionAction::Units(units) => {
+                        *self.units.write().await = units;
+                    }
+                }
+            } else if let Some((toionum, action, seq)) = handle_packet(packet) {
+                let origin = seq.map(|seq| (seq, Origin::Osc));
+                let ack = match action {
+                    Action::Command(cmd) => dispatch(connected, toionum, cmd, origin).await,
+                    Action::Steer(controller) => {
+                        steer(connected, toionum, controller, origin).await
+                    }
+                    Action::Query(query) => self.query(connected, toionum, query, seq).await,
+                };
+                if let Some((seq, status)) = ack {
+                    self.report(self.send_ack(toionum, seq, status).await);
+                }
+            } else {
+                self.errors.rejected.fetch_add(1, Ordering::Relaxed);
+            }
+        } else {
+            self.errors.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sends what the bridge knows about the toio with the given ID, and the
+    /// sequence number to acknowledge if there is one
+    async fn query(
+        &self,
+        connected: &Connected,
+        toionum: usize,
+        query: Query,
+        seq: Option<u32>,
+    ) -> Option<(u32, i32)> {
+        let toio = connected.read().await.get(toionum)?.clone();
+        match query {
+            Query::Odometry => {
+                let odometry = toio.read().await.get_odometry();
+                let odometry = odometry.read().await.at(Instant::now());
+                self.report(self.send_odometry(toionum, &odometry).await);
+            }
+            Query::State => {
+                let state = toio.read().await.get_state();
+                let state = state.read().await.clone();
+                let position = self.to_units(state.world).await;
+                self.report(self.send(&encode_state(toionum, &state, position)).await);
+            }
+        }
+        return seq.map(|seq| (seq, 0));
+    }
+
+    /// Handles the incoming packets of a recording again with their original
+    /// timing divided by the speed, sending commands recorded for one cube to
+    /// another where they are remapped. Waits until every cube the recording
+    /// commands is connected before starting, or until a timeout.
+    pub async fn replay(
+        &self,
+        connected: Connected,
+        swarm: Arc<RwLock<Swarm>>,
+        records: Vec<Record>,
+        speed: f32,
+        remap: &HashMap<usize, usize>,
+    ) {
+        let mut packets = vec![];
+        let mut cubes = 0;
+        for record in records {
+            if record.direction != Direction::Incoming {
+                continue;
+            }
+            let Ok((_, mut packet)) = rosc::decoder::decode_udp(&record.packet) else {
+                continue;
+            };
+
+            remap_packet(&mut packet, remap, &mut cubes);
+            if let Ok(bytes) = encoder::encode(&packet) {
+                packets.push((record.at, bytes));
+            }
+        }
+
+        let waiting = Instant::now();
+        while connected.read().await.len() < cubes {
+            if waiting.elapsed() > REPLAY_TIMEOUT {
+                eprintln!(
+                    "Only {} of the {} toios in the recording connected, replaying anyway",
+                    connected.read().await.len(),
+                    cubes
+                );
+                break;
+            }
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = sleep(Duration::from_millis(100)) => {}
+            }
+        }
+
+        let start = Instant::now();
+        let speed = speed.max(0.01);
+        for (at, bytes) in packets {
+            let at = start + at.div_f32(speed);
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = sleep_until(at.into()) => {}
+            }
+            // replayed packets are not recorded again
+            self.process(&connected, &swarm, &bytes).await;
+        }
+    }
+
+    /// Sends a target to every toio on the mat so that together they form the
+    /// given goals, which are reported as done once every toio responds, or
+    /// has left the mat or disconnected, or after a timeout. The targets are
+    /// driven by the toios themselves, so they do not avoid each other even
+    /// when avoidance is on.
+    ///
+    /// Toios are matched to goals in the world frame of the layout, in mat
+    /// units like the positions in the swarm. Goals in mat coordinates that are
+    /// not on a mat in the layout are used as they are, like the positions of
+    /// toios that are not on one, while other goals off the layout are
+    /// rejected.
+    async fn form(
+        &self,
+        connected: &Connected,
+        swarm: &Arc<RwLock<Swarm>>,
+        goals: Vec<(f32, f32, f32)>,
+    ) {
+        let units = *self.units.read().await;
+        let layout = self.layout.read().await.clone();
+        let mut goal_points = vec![];
+        let mut mat_goals = vec![];
+        for (x, y, angle) in goals {
+            let goal = match layout.place(x, y, angle, units) {
+                Some(world) => {
+                    let placement = &layout.placements[world.mat];
+                    placement
+                        .from_world(world.x, world.y)
+                        .map(|(mat_x, mat_y)| {
+                            let point = (world.x / MM_PER_UNIT, world.y / MM_PER_UNIT);
+                            (
+                                point,
+                                (mat_x, mat_y, placement.from_world_angle(world.theta)),
+                            )
+                        })
+                }
+                None if units == Units::Raw => Some(((x, y), (x as u16, y as u16, angle))),
+                None => None,
+            };
+            let Some((point, mat_goal)) = goal else {
+                self.errors.rejected.fetch_add(1, Ordering::Relaxed);
+                return;
+            };
+            goal_points.push(point);
+            mat_goals.push(mat_goal);
+        }
+
+        let positions = swarm.read().await.positions();
+        let points: Vec<(f32, f32)> = positions.iter().map(|(_, x, y)| (*x, *y)).collect();
+        let assignment = assign(&points, &goal_points);
+
+        // pick every control ID before sending so that no response is missed
+        let mut formation = Formation::new();
+        let mut targets = vec![];
+        {
+            let connected_read = connected.read().await;
+            for ((id, _, _), goal) in positions.iter().zip(assignment) {
+                let (Some(goal), Some(toio)) = (goal, connected_read.get(*id)) else {
+                    continue;
+                };
+                let control = toio.read().await.toio.control_id();
+                formation.add(*id, control);
+                targets.push((*id, control, goal));
+            }
+        }
+
+        if targets.is_empty() {
+            self.report(self.send(&encode_formation_done(0, 0)).await);
+            return;
+        }
+        let started = formation.started();
+        *self.formation.write().await = Some(formation);
+
+        // report the formation as done if some toio never responds
+        let server = self.clone();
+        tokio::spawn(async move {
+            sleep(FORMATION_TIMEOUT).await;
+            let mut formation = server.formation.write().await;
+            if let Some(abandoned) = formation.as_mut().filter(|f| f.started() == started) {
+                let (arrived, assigned) = abandoned.abandon();
+                *formation = None;
+                drop(formation);
+                server.report(server.send_formation_done(arrived, assigned).await);
+            }
+        });
+
+        for (id, control, goal) in targets {
+            let (x, y, angle) = mat_goals[goal];
+            let cmd = Command::MotorTarget {
+                control,
+                timeout: 0,
+                move_type: 0,
+                max_speed: 80,
+                speed_change: 0,
+                x_target: x,
+                y_target: y,
+                theta_target: TargetAngle::Absolute(angle.rem_euclid(360.0) as u16),
+            };
+            dispatch(connected, id, cmd, None).await;
+            self.report(self.send(&encode_formation_assign(id, goal)).await);
+        }
+    }
+
+    /// Records the response of the toio with the given ID to a target command,
+    /// and sends that the formation is done if it was the last toio in it
+    pub async fn respond_formation(&self, id: usize, control: u8, response: u8) {
+        let mut formation = self.formation.write().await;
+        let formed = match formation.as_mut() {
+            Some(waiting) => waiting.respond(id, control, response),
+            None => None,
+        };
+        if let Some((arrived, assigned)) = formed {
+            *formation = None;
+            drop(formation);
+            self.report(self.send_formation_done(arrived, assigned).await);
+        }
+    }
+
+    /// Records that the toio with the given ID will not reach its goal in the
+    /// formation, and sends that the formation is done if it was the last toio
+    pub async fn leave_formation(&self, id: usize) {
+        let mut formation = self.formation.write().await;
+        let formed = match formation.as_mut() {
+            Some(waiting) => waiting.leave(id),
+            None => None,
+        };
+        if let Some((arrived, assigned)) = formed {
+            *formation = None;
+            drop(formation);
+            self.report(self.send_formation_done(arrived, assigned).await);
+        }
+    }
+
+    /// Sends that every toio in a formation has responded
+    async fn send_formation_done(&self, arrived: usize, assigned: usize) -> io::Result<()> {
+        return self.send(&encode_formation_done(arrived, assigned)).await;
+    }
+
+    /// Places mat coordinates in the world frame of the layout, following a
+    /// toio from where it was last as described for [`MatLayout::locate`]
+    pub async fn locate(
+        &self,
+        x: u16,
+        y: u16,
+        theta: u16,
+        last: Option<&WorldPosition>,
+    ) -> Option<WorldPosition> {
+        return self.layout.read().await.locate(x, y, theta, last);
+    }
+
+    /// Converts a position in the world frame into the units the server is
+    /// using, with the angle in whole degrees, or returns None when it is
+    /// using mat coordinates or there is no position
+    async fn to_units(&self, world: Option<WorldPosition>) -> Option<(f32, f32, u16)> {
+        let units = *self.units.read().await;
+        let world = world.filter(|_| units != Units::Raw)?;
+        let (x, y) = self
+            .layout
+            .read()
+            .await
+            .world_to_units(world.x, world.y, units);
+        return Some((x, y, world.theta.round() as u16 % 360));
+    }
+
+    /// Sends an update from the toio with the given ID. A position is sent in
+    /// the world frame of the layout when the server is not using mat
+    /// coordinates, given where the toio is in it. Positions that are not on a
+    /// mat in the layout are only sent in mat coordinates.
+    pub async fn send_update(
+        &self,
+        id: usize,
+        update: Update,
+        world: Option<WorldPosition>,
+    ) -> io::Result<()> {
+        if let Update::Position { .. } = update {
+            if let Some((x, y, theta)) = self.to_units(world).await {
+                return self.send(&encode_position(id, x, y, theta)).await;
+            }
+        }
+
+        if let Some(packet) = encode_update(id, update) {
+            self.send(&packet).await?;
+        }
+        return Ok(());
+    }
+
+    /// Sends a gesture made with the button of the toio with the given ID
+    pub async fn send_button(&self, id: usize, event: ButtonEvent) -> io::Result<()> {
+        return self.send(&encode_button(id, event)).await;
+    }
+
+    /// Sends something that happened to the toio with the given ID
+    pub async fn send_event(&self, id: usize, event: CubeEvent) -> io::Result<()> {
+        return self.send(&encode_event(id, event)).await;
+    }
+
+    /// Sends the estimated pose of the toio with the given ID. Like positions,
+    /// the pose is converted into the world frame of the layout when the server
+    /// is not using mat coordinates, taking it to be on the mat the toio was
+    /// last placed on, and is only sent in mat units when it was never placed.
+    pub async fn send_pose(
+        &self,
+        id: usize,
+        pose: Pose,
+        world: Option<WorldPosition>,
+    ) -> io::Result<()> {
+        let units = *self.units.read().await;
+        let Some(world) = world.filter(|_| units != Units::Raw) else {
+            return self.send(&encode_pose(id, pose)).await;
+        };
+
+        let layout = self.layout.read().await;
+        let placement = &layout.placements[world.mat];
+        let bounds = placement.mat.bounds();
+        let (x, y) =
+            placement.to_world_vector(pose.x - bounds.x_min as f32, pose.y - bounds.y_min as f32);
+        let (x, y) = layout.world_to_units(placement.x + x, placement.y + y, units);
+        let (vx, vy) = placement.to_world_vector(pose.vx, pose.vy);
+        let (vx, vy) = layout.world_to_units(vx, vy, units);
+        let pose = Pose {
+            x,
+            y,
+            theta: placement.to_world_angle(pose.theta),
+            vx,
+            vy,
+            omega: pose.omega,
+        };
+        drop(layout);
+        return self.send(&encode_pose(id, pose)).await;
+    }
+
+    /// Sends how far the toio with the given ID has driven
+    pub async fn send_odometry(&self, id: usize, odometry: &Odometry) -> io::Result<()> {
+        return self.send(&encode_odometry(id, odometry)).await;
+    }
+
+    /// Sends the name of the card or sticker the toio with the given ID was
+    /// placed on
+    pub async fn send_card(&self, id: usize, name: &str) -> io::Result<()> {
+        return self.send(&encode_card(id, name)).await;
+    }
+
+    /// Sends the outcome of a command that was sent with a sequence number
+    pub async fn send_ack(&self, id: usize, seq: u32, status: i32) -> io::Result<()> {
+        return self.send(&encode_ack(id, seq, status)).await;
+    }
+
+    /// Sends how far along its path the toio with the given ID is
+    pub async fn send_path_progress(&self, id: usize, percent: i32) -> io::Result<()> {
+        return self.send(&encode_path_progress(id, percent)).await;
+    }
+
+    /// Sends that the toio with the given ID has reached the end of its path
+    pub async fn send_path_done(&self, id: usize) -> io::Result<()> {
+        return self.send(&encode_path_done(id)).await;
+    }
+
+    /// Sends that the toio with the given ID has stopped going to a point
+    pub async fn send_goto_done(&self, id: usize, status: i32) -> io::Result<()> {
+        return self.send(&encode_goto_done(id, status)).await;
+    }
+
+    /// Counts an error from sending a packet
+    pub fn report(&self, result: io::Result<()>) {
+        if result.is_err() {
+            self.errors.send.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Errors the server has had
+    pub fn get_errors(&self) -> Arc<ServerErrors> {
+        return self.errors.clone();
+    }
+
+    /// Stops all of the listening tasks
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    async fn send(&self, packet: &OscPacket) -> io::Result<()> {
+        let msg = encoder::encode(packet)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Outgoing, &msg);
+        }
+
+        // there are no receivers when no TCP clients are connected
+        if !self.listeners.is_empty() {
+            let _ = self.streams.send(slip::encode(&msg));
+        }
+
+        // send from the first socket that can reach the remote address
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| {
+                socket
+                    .local_addr()
+                    .is_ok_and(|addr| addr.is_ipv4() == self.to_addr.is_ipv4())
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "no socket bound for the remote address family",
+                )
+            })?;
+
+        socket.send_to(&msg, self.to_addr).await?;
+        return Ok(());
+    }
+}
+
+/// Creates a non-blocking socket bound to the given address
+pub(crate) fn bind_socket(addr: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    return Ok(socket);
+}
+
+/// Sends the commands of a recorded packet to the cubes they are remapped to,
+/// including the messages in bundles, and counts the cubes it commands. Every
+/// command other than a session command starts with a cube ID.
+fn remap_packet(packet: &mut OscPacket, remap: &HashMap<usize, usize>, cubes: &mut usize) {
+    if handle_session_packet(packet).is_some() {
+        return;
+    }
+    match packet {
+        OscPacket::Message(msg) => {
+            let Some(OscType::Int(id)) = msg.args.first_mut() else {
+                return;
+            };
+            let Ok(recorded) = usize::try_from(*id) else {
+                return;
+            };
+            let cube = *remap.get(&recorded).unwrap_or(&recorded);
+            *id = cube as i32;
+            *cubes = (*cubes).max(cube + 1);
+        }
+        OscPacket::Bundle(bundle) => {
+            for packet in &mut bundle.content {
+                remap_packet(packet, remap, cubes);
+            }
+        }
+    }
+}
+
+/// Sends a command to the toio with the given ID. If the command has a
+/// sequence number and its outcome is already known, returns the sequence
+/// number and status to acknowledge. Target commands are instead acknowledged
+/// once the toio responds, to wherever the command came from.
+///
+/// MultiTarget commands with more targets than fit in one packet are split
+/// into parts, which are sent as described for [`TargetQueue`]. Any target
+/// command drops the rest of the path that was being sent to the toio.
+pub async fn dispatch(
+    connected: &Connected,
+    toionum: usize,
+    cmd: Command,
+    seq: Option<(u32, Origin)>,
+) -> Option<(u32, i32)> {
+    let connected_read = connected.read().await;
+    if toionum >= connected_read.len() {
+        return None;
+    }
+    let toio = connected_read[toionum].read().await;
+
+    let last_command = toio.get_last_command();
+    let mut last_command_write = last_command.write().await;
+    *last_command_write = Some(SystemTime::now());
+
+    // motor speed notifications do not say which way the wheels turn
+    let odometry = toio.get_odometry();
+    odometry.write().await.command(&cmd, Instant::now());
+
+    let mut commands = split_multi_target(cmd);
+    let controls: Vec<Option<u8>> = commands.iter().map(target_control).collect();
+
+    // queue the later parts and wait on every part before sending any, so that
+    // each response is expected by the time it arrives
+    let queued = toio.get_queued_targets();
+    let pending = toio.get_pending_targets();
+    if controls[0].is_some() {
+        commands = queued.write().await.start(commands);
+        if let Some(seq) = seq {
+            let mut pending_write = pending.write().await;
+            for control in controls.iter().flatten() {
+                pending_write.insert(*control, seq);
+            }
+        }
+    }
+
+    let mut result = Ok(());
+    for command in commands {
+        if result.is_ok() {
+            result = toio.toio.send_command(command).await;
+        }
+    }
+
+    if result.is_err() && controls[0].is_some() {
+        queued.write().await.clear();
+        if let Some(seq) = seq {
+            pending
+                .write()
+                .await
+                .retain(|_, pending_seq| *pending_seq != seq);
+        }
+    }
+
+    return match (seq.map(|(seq, _)| seq), result, controls[0]) {
+        (None, _, _) => None,
+        // target commands are acknowledged once the toio responds to every part
+        (Some(_), Ok(_), Some(_)) => None,
+        (Some(seq), Ok(_), None) => Some((seq, 0)),
+        (Some(seq), Err(_), _) => Some((seq, -1)),
+    };
+}
+
+/// Sends the next part of a long MultiTarget command to the toio with the given
+/// ID. If it cannot be sent, the rest of the path is dropped and, if the
+/// command had a sequence number, returns it and where the command came from
+/// to acknowledge as failed.
+pub async fn send_part(
+    connected: &Connected,
+    toionum: usize,
+    part: Command,
+) -> Option<(u32, Origin, i32)> {
+    let connected_read = connected.read().await;
+    let toio = connected_read.get(toionum)?.read().await;
+
+    let control = target_control(&part);
+    if toio.toio.send_command(part).await.is_ok() {
+        return None;
+    }
+
+    toio.get_queued_targets().write().await.clear();
+    let pending = toio.get_pending_targets();
+    let mut pending_write = pending.write().await;
+    let (seq, origin) = pending_write.remove(&control?)?;
+    pending_write.retain(|_, pending| *pending != (seq, origin));
+    return Some((seq, origin, -1));
+}
+
+/// Starts steering the toio with the given ID from the host, replacing any
+/// controller that was already steering it, or stops it when there is no
+/// controller. The toio is steered as its position is updated. If there is a
+/// sequence number, returns it to acknowledge.
+pub async fn steer(
+    connected: &Connected,
+    toionum: usize,
+    controller: Option<Controller>,
+    seq: Option<(u32, Origin)>,
+) -> Option<(u32, i32)> {
+    let stop = controller.is_none();
+    {
+        let connected_read = connected.read().await;
+        let toio = connected_read.get(toionum)?.read().await;
+        *toio.get_controller().write().await = controller;
+    }
+
+    if stop {
+        return dispatch(connected, toionum, Command::wheels(0, 0), seq).await;
+    }
+    return seq.map(|(seq, _)| (seq, 0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::{OscBundle, OscMessage, OscTime};
+
+    fn message(addr: &str, args: Vec<OscType>) -> OscPacket {
+        return OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        });
+    }
+
+    #[test]
+    fn remaps_recorded_cube_ids() {
+        let remap = HashMap::from([(0, 2)]);
+        let mut cubes = 0;
+
+        let mut packet = message("/led", vec![OscType::Int(0), OscType::Int(255)]);
+        remap_packet(&mut packet, &remap, &mut cubes);
+        assert_eq!(
+            packet,
+            message("/led", vec![OscType::Int(2), OscType::Int(255)])
+        );
+        assert_eq!(cubes, 3);
+
+        // negative IDs are left alone and do not count as cubes
+        let mut packet = message("/led", vec![OscType::Int(-1)]);
+        remap_packet(&mut packet, &remap, &mut cubes);
+        assert_eq!(packet, message("/led", vec![OscType::Int(-1)]));
+        assert_eq!(cubes, 3);
+
+        let mut packet = OscPacket::Bundle(OscBundle {
+            timetag: OscTime::from((0, 1)),
+            content: vec![
+                message("/led", vec![OscType::Int(0)]),
+                message("/led", vec![OscType::Int(4)]),
+            ],
+        });
+        remap_packet(&mut packet, &remap, &mut cubes);
+        let OscPacket::Bundle(bundle) = packet else {
+            panic!("expected a bundle");
+        };
+        assert_eq!(
+            bundle.content,
+            vec![
+                message("/led", vec![OscType::Int(2)]),
+                message("/led", vec![OscType::Int(4)]),
+            ]
+        );
+        assert_eq!(cubes, 5);
+    }
+}
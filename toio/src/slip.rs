@@ -0,0 +1,136 @@
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// Longest packet that is decoded, which is as large as any UDP packet
+pub const MAX_FRAME: usize = 65536;
+
+/// Frames a packet with SLIP (RFC 1055) to send over a stream. As in OSC 1.1,
+/// an END byte is used at both the start and end of the frame.
+pub fn encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 2);
+    frame.push(END);
+
+    for byte in packet.iter() {
+        match *byte {
+            END => frame.extend_from_slice(&[ESC, ESC_END]),
+            ESC => frame.extend_from_slice(&[ESC, ESC_ESC]),
+            byte => frame.push(byte),
+        }
+    }
+
+    frame.push(END);
+    return frame;
+}
+
+/// Collects bytes from a stream until they form complete packets. A frame
+/// longer than `MAX_FRAME` is dropped and counted, along with the rest of its
+/// bytes up to the next END.
+#[derive(Default)]
+pub struct SlipDecoder {
+    buffer: Vec<u8>,
+    escaped: bool,
+    overflowed: bool,
+    dropped: usize,
+}
+
+impl SlipDecoder {
+    pub fn new() -> SlipDecoder {
+        return SlipDecoder::default();
+    }
+
+    /// Number of frames that were dropped for being too long
+    pub fn dropped(&self) -> usize {
+        return self.dropped;
+    }
+
+    /// Adds bytes read from the stream and returns every packet they complete
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut packets = vec![];
+
+        for byte in bytes.iter() {
+            // skip the rest of a frame that is too long
+            if self.overflowed {
+                if *byte == END {
+                    self.overflowed = false;
+                }
+                continue;
+            }
+
+            if self.escaped {
+                self.escaped = false;
+                match *byte {
+                    ESC_END => self.buffer.push(END),
+                    ESC_ESC => self.buffer.push(ESC),
+                    // protocol violation, keep the byte as is
+                    byte => self.buffer.push(byte),
+                }
+                continue;
+            }
+
+            match *byte {
+                END => {
+                    // empty frames come from the leading END of each packet
+                    if !self.buffer.is_empty() {
+                        packets.push(std::mem::take(&mut self.buffer));
+                    }
+                }
+                ESC => self.escaped = true,
+                byte => self.buffer.push(byte),
+            }
+
+            if self.buffer.len() > MAX_FRAME {
+                self.dropped += 1;
+                self.buffer = vec![];
+                self.escaped = false;
+                self.overflowed = true;
+            }
+        }
+
+        return packets;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_escaped_bytes() {
+        let packet = vec![1, END, 2, ESC, ESC_END, ESC_ESC, 3];
+        let frame = encode(&packet);
+        assert_eq!(
+            frame,
+            vec![END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, ESC_END, ESC_ESC, 3, END]
+        );
+        assert_eq!(SlipDecoder::new().decode(&frame), vec![packet]);
+    }
+
+    #[test]
+    fn decodes_split_and_back_to_back_frames() {
+        let mut decoder = SlipDecoder::new();
+        let frame = encode(&[1, END, 2]);
+
+        // a frame split between reads, including between ESC and ESC_END
+        assert!(decoder.decode(&frame[..3]).is_empty());
+        assert_eq!(decoder.decode(&frame[3..]), vec![vec![1, END, 2]]);
+
+        // frames sharing an END, and repeated ENDs between them
+        assert_eq!(
+            decoder.decode(&[END, 4, END, 5, END, END, END, 6, END]),
+            vec![vec![4], vec![5], vec![6]]
+        );
+    }
+
+    #[test]
+    fn drops_frames_that_are_too_long() {
+        let mut decoder = SlipDecoder::new();
+        assert!(decoder.decode(&vec![7; MAX_FRAME + 1]).is_empty());
+        assert!(decoder.decode(&[7, 7]).is_empty());
+        assert_eq!(decoder.dropped(), 1);
+
+        // decoding resumes from the END after the long frame
+        assert_eq!(decoder.decode(&[END, 8, END]), vec![vec![8]]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// First Standard ID of the number cards, which count up from 0
+const NUMBER_BASE: u32 = 3670320;
+
+/// First Standard ID of the letter cards, which count up from A
+const LETTER_BASE: u32 = 3670337;
+
+/// Cards and stickers printed with a Standard ID, which a toio reads when it is
+/// placed on them, as listed at
+/// https://toio.github.io/toio-spec/en/docs/hardware_standard_id
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StandardId {
+    /// Typhoon card from the craft fighter game in toio Collection, on the
+    /// back of Rush
+    Typhoon,
+    /// Rush card from the craft fighter game in toio Collection
+    Rush,
+    /// Auto tackle card from the craft fighter game in toio Collection, on
+    /// the back of Random
+    AutoTackle,
+    /// Random card from the craft fighter game in toio Collection
+    Random,
+    /// Push power up card from the craft fighter game in toio Collection, on
+    /// the back of Stroke power up
+    PushPowerUp,
+    /// Stroke power up card from the craft fighter game in toio Collection
+    StrokePowerUp,
+    /// Side attack card from the craft fighter game in toio Collection, on
+    /// the back of Easy mode
+    SideAttack,
+    /// Easy mode card from the craft fighter game in toio Collection
+    EasyMode,
+    /// Speed up sticker from toio Collection, on the back of Speed down
+    SpeedUp,
+    /// Speed down sticker from toio Collection
+    SpeedDown,
+    /// Wobble sticker from toio Collection, on the back of Panic
+    Wobble,
+    /// Panic sticker from toio Collection
+    Panic,
+    /// Spin sticker from toio Collection, on the back of Shock
+    Spin,
+    /// Shock sticker from toio Collection
+    Shock,
+    /// Simple card with an exclamation mark, on the back of the up arrow
+    Exclamation,
+    /// Simple card with a question mark, on the back of the down arrow
+    Question,
+    /// Simple card with a plus sign, on the back of the right arrow
+    Plus,
+    /// Simple card with a minus sign, on the back of the left arrow
+    Minus,
+    /// Simple card with an arrow pointing up
+    ArrowUp,
+    /// Simple card with an arrow pointing down
+    ArrowDown,
+    /// Simple card with an arrow pointing right
+    ArrowRight,
+    /// Simple card with an arrow pointing left
+    ArrowLeft,
+    /// Number card from 0 to 9
+    Number(u8),
+    /// Letter card from A to Z
+    Letter(char),
+    /// Any other Standard ID
+    Unknown(u32),
+}
+
+/// Named cards and stickers that are not in the built in table, or that are
+/// given a different name, by Standard ID
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StandardNames {
+    names: HashMap<u32, String>,
+}
+
+/// Standard IDs of the cards and stickers that are not numbers or letters. The
+/// back of each card or sticker is 38 after the front in toio Collection, and
+/// 36 after it for simple cards.
+const TABLE: [(u32, StandardId); 22] = [
+    (3670016, StandardId::Typhoon),
+    (3670054, StandardId::Rush),
+    (3670018, StandardId::AutoTackle),
+    (3670056, StandardId::Random),
+    (3670020, StandardId::PushPowerUp),
+    (3670058, StandardId::StrokePowerUp),
+    (3670022, StandardId::SideAttack),
+    (3670060, StandardId::EasyMode),
+    (3670024, StandardId::SpeedUp),
+    (3670062, StandardId::SpeedDown),
+    (3670026, StandardId::Wobble),
+    (3670064, StandardId::Panic),
+    (3670028, StandardId::Spin),
+    (3670066, StandardId::Shock),
+    (3670330, StandardId::Exclamation),
+    (3670366, StandardId::ArrowUp),
+    (3670331, StandardId::Question),
+    (3670367, StandardId::ArrowDown),
+    (3670332, StandardId::Plus),
+    (3670368, StandardId::ArrowRight),
+    (3670333, StandardId::Minus),
+    (3670369, StandardId::ArrowLeft),
+];
+
+impl StandardId {
+    /// Looks up the card or sticker with the given Standard ID
+    pub fn from_id(standard: u32) -> StandardId {
+        if let Some((_, item)) = TABLE.iter().find(|(id, _)| *id == standard) {
+            return *item;
+        }
+        if (NUMBER_BASE..NUMBER_BASE + 10).contains(&standard) {
+            return StandardId::Number((standard - NUMBER_BASE) as u8);
+        }
+        if (LETTER_BASE..LETTER_BASE + 26).contains(&standard) {
+            return StandardId::Letter((b'A' + (standard - LETTER_BASE) as u8) as char);
+        }
+        return StandardId::Unknown(standard);
+    }
+
+    /// Standard ID printed on the card or sticker
+    pub fn id(&self) -> u32 {
+        return match self {
+            StandardId::Number(number) => NUMBER_BASE + *number as u32,
+            StandardId::Letter(letter) => LETTER_BASE + (*letter as u8 - b'A') as u32,
+            StandardId::Unknown(standard) => *standard,
+            item => TABLE
+                .iter()
+                .find(|(_, known)| known == item)
+                .map_or(0, |(id, _)| *id),
+        };
+    }
+}
+
+impl From<u32> for StandardId {
+    fn from(standard: u32) -> StandardId {
+        return StandardId::from_id(standard);
+    }
+}
+
+impl fmt::Display for StandardId {
+    /// Writes the name of the card or sticker, such as `arrow_up`, `number_3`
+    /// or `letter_a`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StandardId::Typhoon => write!(f, "typhoon"),
+            StandardId::Rush => write!(f, "rush"),
+            StandardId::AutoTackle => write!(f, "auto_tackle"),
+            StandardId::Random => write!(f, "random"),
+            StandardId::PushPowerUp => write!(f, "push_power_up"),
+            StandardId::StrokePowerUp => write!(f, "stroke_power_up"),
+            StandardId::SideAttack => write!(f, "side_attack"),
+            StandardId::EasyMode => write!(f, "easy_mode"),
+            StandardId::SpeedUp => write!(f, "speed_up"),
+            StandardId::SpeedDown => write!(f, "speed_down"),
+            StandardId::Wobble => write!(f, "wobble"),
+            StandardId::Panic => write!(f, "panic"),
+            StandardId::Spin => write!(f, "spin"),
+            StandardId::Shock => write!(f, "shock"),
+            StandardId::Exclamation => write!(f, "exclamation"),
+            StandardId::Question => write!(f, "question"),
+            StandardId::Plus => write!(f, "plus"),
+            StandardId::Minus => write!(f, "minus"),
+            StandardId::ArrowUp => write!(f, "arrow_up"),
+            StandardId::ArrowDown => write!(f, "arrow_down"),
+            StandardId::ArrowRight => write!(f, "arrow_right"),
+            StandardId::ArrowLeft => write!(f, "arrow_left"),
+            StandardId::Number(number) => write!(f, "number_{}", number),
+            StandardId::Letter(letter) => write!(f, "letter_{}", letter.to_ascii_lowercase()),
+            StandardId::Unknown(standard) => write!(f, "unknown_{}", standard),
+        }
+    }
+}
+
+impl StandardNames {
+    pub fn new() -> StandardNames {
+        return StandardNames::default();
+    }
+
+    /// Names the card or sticker with the given Standard ID
+    pub fn insert(&mut self, standard: u32, name: &str) {
+        self.names.insert(standard, name.to_string());
+    }
+
+    /// Name of the card or sticker with the given Standard ID, from the names
+    /// that were added or else the built in table
+    pub fn name(&self, standard: u32) -> Option<String> {
+        if let Some(name) = self.names.get(&standard) {
+            return Some(name.clone());
+        }
+        return match StandardId::from_id(standard) {
+            StandardId::Unknown(_) => None,
+            item => Some(item.to_string()),
+        };
+    }
+}
+
+impl FromStr for StandardNames {
+    type Err = String;
+
+    /// Parses a mapping file, with a line for each card giving its Standard ID
+    /// and name, such as `3670337 start`. Blank lines and lines starting with
+    /// `#` are skipped.
+    fn from_str(text: &str) -> Result<StandardNames, String> {
+        let mut names = StandardNames::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((standard, name)) = line.split_once(char::is_whitespace) else {
+                return Err(format!("line {}: expected id name", number + 1));
+            };
+            let standard = standard
+                .parse()
+                .map_err(|_| format!("line {}: invalid id {}", number + 1, standard))?;
+            names.insert(standard, name.trim());
+        }
+
+        return Ok(names);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins_the_table_to_the_spec() {
+        let names: Vec<(u32, String)> = TABLE
+            .iter()
+            .map(|(id, item)| (*id, item.to_string()))
+            .collect();
+        let expected = [
+            (3670016, "typhoon"),
+            (3670054, "rush"),
+            (3670018, "auto_tackle"),
+            (3670056, "random"),
+            (3670020, "push_power_up"),
+            (3670058, "stroke_power_up"),
+            (3670022, "side_attack"),
+            (3670060, "easy_mode"),
+            (3670024, "speed_up"),
+            (3670062, "speed_down"),
+            (3670026, "wobble"),
+            (3670064, "panic"),
+            (3670028, "spin"),
+            (3670066, "shock"),
+            (3670330, "exclamation"),
+            (3670366, "arrow_up"),
+            (3670331, "question"),
+            (3670367, "arrow_down"),
+            (3670332, "plus"),
+            (3670368, "arrow_right"),
+            (3670333, "minus"),
+            (3670369, "arrow_left"),
+        ];
+        assert_eq!(
+            names,
+            expected
+                .iter()
+                .map(|(id, name)| (*id, name.to_string()))
+                .collect::<Vec<_>>()
+        );
+
+        // every item has its own ID, and none of them overlap the numbers and
+        // letters
+        for (i, (id, item)) in TABLE.iter().enumerate() {
+            assert_eq!(StandardId::from_id(*id), *item);
+            assert!(TABLE[i + 1..].iter().all(|(other, _)| other != id));
+        }
+        assert_eq!(StandardId::from_id(3670329), StandardId::Number(9));
+        assert_eq!(StandardId::from_id(3670362), StandardId::Letter('Z'));
+    }
+
+    #[test]
+    fn looks_up_known_ids() {
+        for item in [
+            StandardId::Typhoon,
+            StandardId::ArrowUp,
+            StandardId::Number(0),
+            StandardId::Number(9),
+            StandardId::Letter('A'),
+            StandardId::Letter('Z'),
+            StandardId::Unknown(42),
+        ] {
+            assert_eq!(StandardId::from_id(item.id()), item);
+        }
+        assert_eq!(StandardId::from_id(3670339).to_string(), "letter_c");
+        assert_eq!(StandardId::from_id(3670366).to_string(), "arrow_up");
+    }
+
+    #[test]
+    fn names_cards_from_a_mapping_file() {
+        let names: StandardNames = "# my cards\n3670337 start\n\n12345 goal line\n"
+            .parse()
+            .unwrap();
+        assert_eq!(names.name(3670337), Some("start".to_string()));
+        assert_eq!(names.name(12345), Some("goal line".to_string()));
+        assert_eq!(names.name(3670338), Some("letter_b".to_string()));
+        assert_eq!(names.name(1), None);
+        assert!("abc start".parse::<StandardNames>().is_err());
+    }
+}
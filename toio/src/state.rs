@@ -0,0 +1,106 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::mat::WorldPosition;
+use crate::toio::Update;
+
+/// Latest of everything a toio has reported, kept up to date from its updates
+/// so that it can be read as one consistent snapshot
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CubeState {
+    pub battery: Option<u8>,
+    /// Latest position and angle on the mat, kept after the toio leaves it
+    pub x: u16,
+    pub y: u16,
+    pub theta: u16,
+    pub on_mat: bool,
+    /// Latest position in the world frame of the mat layout, if the toio was on
+    /// a mat in the layout, kept after the toio leaves it
+    pub world: Option<WorldPosition>,
+    /// Standard ID the toio is on, if any
+    pub standard: Option<u32>,
+    pub horizontal: bool,
+    pub collision: bool,
+    pub double_tap: bool,
+    pub posture: u8,
+    pub shake: u8,
+    /// Posture angles in degrees
+    pub roll: i16,
+    pub pitch: i16,
+    pub yaw: i16,
+    pub magnetic_state: u8,
+    pub magnetic_strength: u8,
+    pub magnetic_force: (i8, i8, i8),
+    pub button: bool,
+    pub left_speed: u8,
+    pub right_speed: u8,
+}
+
+impl CubeState {
+    pub fn new() -> CubeState {
+        return CubeState::default();
+    }
+
+    /// Records an update from the toio
+    pub fn apply(&mut self, update: &Update) {
+        match *update {
+            Update::Position {
+                x_center,
+                y_center,
+                theta,
+                ..
+            } => {
+                self.x = x_center;
+                self.y = y_center;
+                self.theta = theta;
+                self.on_mat = true;
+            }
+            Update::PositionMissed => self.on_mat = false,
+            Update::Standard { standard, .. } => self.standard = Some(standard),
+            Update::StandardMissed => self.standard = None,
+            Update::Battery { level } => self.battery = Some(level),
+            Update::Button { pressed } => self.button = pressed,
+            Update::Motion {
+                horizontal,
+                collision,
+                double_tap,
+                posture,
+                shake,
+            } => {
+                self.horizontal = horizontal != 0;
+                self.collision = collision != 0;
+                self.double_tap = double_tap != 0;
+                self.posture = posture;
+                self.shake = shake;
+            }
+            Update::PostureEuler { roll, pitch, yaw } => {
+                (self.roll, self.pitch, self.yaw) = (roll, pitch, yaw);
+            }
+            Update::PostureHighPrecisionEuler { roll, pitch, yaw } => {
+                self.roll = roll.round() as i16;
+                self.pitch = pitch.round() as i16;
+                self.yaw = yaw.round() as i16;
+            }
+            Update::Magnetic {
+                state,
+                strength,
+                forcex,
+                forcey,
+                forcez,
+            } => {
+                self.magnetic_state = state;
+                self.magnetic_strength = strength;
+                self.magnetic_force = (forcex, forcey, forcez);
+            }
+            Update::MotorSpeed {
+                left_speed,
+                right_speed,
+            } => {
+                self.left_speed = left_speed;
+                self.right_speed = right_speed;
+            }
+            _ => {}
+        }
+    }
+}
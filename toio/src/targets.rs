@@ -0,0 +1,157 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::toio::Command;
+
+/// Control ID of a command that the toio responds to once it is done
+pub fn target_control(cmd: &Command) -> Option<u8> {
+    return match cmd {
+        Command::MotorTarget { control, .. } | Command::MultiTarget { control, .. } => {
+            Some(*control)
+        }
+        _ => None,
+    };
+}
+
+/// Parts of a long MultiTarget path that have not been sent to a toio yet. The
+/// first two parts are sent at once so that the toio moves from one to the next
+/// without stopping, and each later part is sent once the part two before it
+/// finishes. Each path has its own ID, so that responses to a path that was
+/// dropped never send parts of it, even when a later command reuses the same
+/// control IDs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetQueue {
+    /// ID of the path being sent, which changes whenever a path is started or
+    /// dropped
+    path: u32,
+    /// Path of each part that has been sent and has not responded, by control ID
+    sent: HashMap<u8, u32>,
+    /// Parts of the path that are still to be sent, in order
+    queued: VecDeque<Command>,
+}
+
+impl TargetQueue {
+    pub fn new() -> TargetQueue {
+        return TargetQueue::default();
+    }
+
+    /// Starts a new target command that has been split into parts, dropping
+    /// the rest of any previous path, and returns the parts to send now. Call
+    /// this before sending them, so that no response can arrive first.
+    pub fn start(&mut self, mut parts: Vec<Command>) -> Vec<Command> {
+        self.clear();
+        self.queued = parts.split_off(parts.len().min(2)).into();
+        for control in parts.iter().filter_map(target_control) {
+            self.sent.insert(control, self.path);
+        }
+        return parts;
+    }
+
+    /// Handles a response from the toio to a target command. Returns the next
+    /// part to send when a part of the current path finished, or drops the
+    /// rest of the path when it did not.
+    pub fn respond(&mut self, control: u8, response: u8) -> Option<Command> {
+        if self.sent.remove(&control) != Some(self.path) {
+            return None;
+        }
+        if response != 0 {
+            self.clear();
+            return None;
+        }
+
+        let next = self.queued.pop_front()?;
+        if let Some(control) = target_control(&next) {
+            self.sent.insert(control, self.path);
+        }
+        return Some(next);
+    }
+
+    /// Drops every part of the current path that has not been sent. Parts that
+    /// were sent belong to an old path from then on, so their responses are
+    /// ignored.
+    pub fn clear(&mut self) {
+        self.path = self.path.wrapping_add(1);
+        self.queued.clear();
+    }
+
+    /// Whether any part of the current path is still to be sent
+    pub fn is_empty(&self) -> bool {
+        return self.queued.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::split_multi_target;
+    use crate::toio::{TargetAngle, TargetCommand, WriteMode};
+
+    fn path(control: u8, targets: usize) -> Vec<Command> {
+        return split_multi_target(Command::MultiTarget {
+            control,
+            timeout: 0,
+            move_type: 0,
+            max_speed: 50,
+            speed_change: 0,
+            op_add: WriteMode::Overwrite,
+            targets: vec![
+                TargetCommand {
+                    x_target: 100,
+                    y_target: 100,
+                    theta_target: TargetAngle::Unchanged,
+                };
+                targets
+            ],
+        });
+    }
+
+    #[test]
+    fn sends_each_part_once_the_part_two_before_finishes() {
+        let mut queue = TargetQueue::new();
+        let parts = path(10, 90);
+        assert_eq!(parts.len(), 4);
+
+        // responses can arrive as soon as the first parts are sent
+        assert_eq!(queue.start(parts.clone()), parts[..2]);
+        assert_eq!(queue.respond(10, 0), Some(parts[2].clone()));
+        assert_eq!(queue.respond(11, 0), Some(parts[3].clone()));
+        assert!(queue.is_empty());
+        assert_eq!(queue.respond(12, 0), None);
+        assert_eq!(queue.respond(13, 0), None);
+    }
+
+    #[test]
+    fn drops_the_rest_of_a_path_that_fails() {
+        let mut queue = TargetQueue::new();
+        let parts = path(10, 120);
+        assert_eq!(parts.len(), 5);
+
+        queue.start(parts.clone());
+        assert_eq!(queue.respond(10, 0), Some(parts[2].clone()));
+        assert_eq!(queue.respond(11, 3), None);
+        assert!(queue.is_empty());
+
+        // a part that was already sent finishing does not send any more
+        assert_eq!(queue.respond(12, 0), None);
+
+        // nor does an unrelated target reusing a control ID of the path
+        queue.start(path(13, 1));
+        assert_eq!(queue.respond(13, 0), None);
+    }
+
+    #[test]
+    fn drops_the_rest_of_a_path_that_is_overwritten() {
+        let mut queue = TargetQueue::new();
+        queue.start(path(10, 120));
+
+        let parts = path(20, 50);
+        assert_eq!(queue.start(parts.clone()), parts);
+        assert!(queue.is_empty());
+
+        // the overwritten path is stopped by the toio, which does not affect
+        // the new one
+        assert_eq!(queue.respond(10, 5), None);
+        assert_eq!(queue.respond(11, 5), None);
+        assert_eq!(queue.respond(20, 0), None);
+        assert_eq!(queue.respond(21, 0), None);
+    }
+}
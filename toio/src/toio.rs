@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 use std::vec;
@@ -12,27 +14,27 @@ use tokio::time::timeout;
 
 use futures::stream::StreamExt;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use uuid::Uuid;
 
+use crate::codec::*;
+use crate::follow::{FollowStep, PathFollower};
+use crate::goto::GoTo;
+use crate::odometry::Odometry;
+use crate::state::CubeState;
+use crate::targets::TargetQueue;
+
 use btleplug::{
     api::{
         Central, CentralEvent, CharPropFlags, Characteristic, Manager as _, Peripheral, ScanFilter,
-        ValueNotification, WriteType,
+        WriteType,
     },
     platform,
     platform::{Adapter, Manager},
 };
 
-pub const SERVICE: Uuid = Uuid::from_u128(0x10B20100_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const POSITION: Uuid = Uuid::from_u128(0x10B20101_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const MOTOR: Uuid = Uuid::from_u128(0x10B20102_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const LIGHT: Uuid = Uuid::from_u128(0x10B20103_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const SOUND: Uuid = Uuid::from_u128(0x10B20104_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const MOTION: Uuid = Uuid::from_u128(0x10B20106_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const BUTTON: Uuid = Uuid::from_u128(0x10B20107_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const BATTERY: Uuid = Uuid::from_u128(0x10B20108_5B3B_4571_9508_CF3EFCD7BBAE);
-pub const CONFIG: Uuid = Uuid::from_u128(0x10B201FF_5B3B_4571_9508_CF3EFCD7BBAE);
-
 const IDARR: [&str; 193] = [
     "Individual ID", //TOIO Num
     "0",             // #1
@@ -229,22 +231,67 @@ const IDARR: [&str; 193] = [
     "n2L",           // #192
 ];
 
+/// Angle of a toio once it reaches a target, as described at
+/// https://toio.github.io/toio-spec/en/docs/ble_motor#angle-of-the-cube-at-the-target-point
+///
+/// With the `serde` feature, an angle is represented as an object with a
+/// `mode` tag and the angle in `degrees`, e.g. `{"mode": "relative", "degrees": -90}`
+/// or `{"mode": "unchanged"}`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "mode", content = "degrees", rename_all = "snake_case")
+)]
+pub enum TargetAngle {
+    /// Absolute angle in degrees, rotating in whichever direction is shorter
+    Absolute(u16),
+    /// Absolute angle in degrees, rotating in the positive direction
+    AbsolutePositive(u16),
+    /// Absolute angle in degrees, rotating in the negative direction
+    AbsoluteNegative(u16),
+    /// Angle in degrees relative to the angle when the command was sent,
+    /// rotating in the direction of its sign
+    Relative(i16),
+    /// Do not rotate at the target
+    Unchanged,
+    /// Keep the angle from when the command was sent
+    Keep,
+}
+
+/// How a MultiTarget command is combined with one that is already running, as
+/// described at https://toio.github.io/toio-spec/en/docs/ble_motor#additional-write-operation-setting
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum WriteMode {
+    /// Stop the running command and start this one
+    Overwrite,
+    /// Start this command once the running command has finished
+    Append,
+}
+
 /// Format for a target to plug into the MotorTarget varient
 /// of the Command enum. By putting multiple of these into a vector,
 /// you can send a a series of targets for a toio to travel to in
 /// a sequence.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TargetCommand {
     pub x_target: u16,
     pub y_target: u16,
-    pub theta_target: u16,
+    pub theta_target: TargetAngle,
 }
 
 /// Format for a RGB color to plug into the MultiLed varient
 /// of the Command enum. By putting multiple of these into a vector,
 /// you can send a a series of colors for a toio to flash on its led
 /// in a sequence.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LedCommand {
     pub duration: u8,
     pub red: u8,
@@ -256,7 +303,8 @@ pub struct LedCommand {
 /// of the Command enum. By putting multiple of these into a vector,
 /// you can send a a series of notes for a toio to play  in
 /// a sequence.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MidiCommand {
     pub duration: u8,
     pub note: u8,
@@ -264,8 +312,19 @@ pub struct MidiCommand {
 }
 
 /// An enum to list out all possible commands to send to a toio
+///
+/// With the `serde` feature, a command is represented as an object with its
+/// fields and a `cmd` tag holding the snake case name of the variant, e.g.
+/// `{"cmd": "led", "duration": 0, "red": 255, "green": 0, "blue": 0}` or
+/// `{"cmd": "sound_off"}`. Lists of targets, lights and notes are arrays of
+/// objects with the fields of `TargetCommand`, `LedCommand` and `MidiCommand`.
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "cmd", rename_all = "snake_case")
+)]
 pub enum Command {
     //Request Commands
     MotionRequest,
@@ -296,7 +355,7 @@ pub enum Command {
         speed_change: u8,
         x_target: u16,
         y_target: u16,
-        theta_target: u16,
+        theta_target: TargetAngle,
     },
     MultiTarget {
         control: u8,
@@ -304,7 +363,7 @@ pub enum Command {
         move_type: u8,
         max_speed: u8,
         speed_change: u8,
-        op_add: u8,
+        op_add: WriteMode,
         targets: Vec<TargetCommand>,
     },
     MotorAcceleration {
@@ -340,11 +399,25 @@ pub enum Command {
         repetitions: u8,
         notes: Vec<MidiCommand>,
     },
+
+    //Config Commands
+    MotorSpeedConfig {
+        enabled: bool,
+    },
 }
 
 /// An enum to list out all possible updates to recieve from a toio
+///
+/// With the `serde` feature, an update is represented as an object with its
+/// fields and an `update` tag holding the snake case name of the variant, e.g.
+/// `{"update": "battery", "level": 80}` or `{"update": "position_missed"}`.
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "update", rename_all = "snake_case")
+)]
 pub enum Update {
     Position {
         x_center: u16,
@@ -371,6 +444,9 @@ pub enum Update {
         left_speed: u8,
         right_speed: u8,
     },
+    MotorSpeedConfigResponse {
+        response: u8,
+    },
     Motion {
         horizontal: u8,
         collision: u8,
@@ -379,9 +455,9 @@ pub enum Update {
         shake: u8,
     },
     PostureEuler {
-        roll: u16,
-        pitch: u16,
-        yaw: u16,
+        roll: i16,
+        pitch: i16,
+        yaw: i16,
     },
     PostureQuaternion {
         w: f32,
@@ -409,8 +485,26 @@ pub enum Update {
     },
 }
 
+/// Controller that steers a toio from the host using its position updates
+#[derive(Clone, Debug, PartialEq)]
+pub enum Controller {
+    /// Following a path
+    Path(PathFollower),
+    /// Going to a point
+    GoTo(GoTo),
+}
+
 pub struct Updates {
-    receiver: Receiver<Update>,
+    receiver: Receiver<Result<Update, DecodeError>>,
+    errors: usize,
+}
+
+/// Where a command with a sequence number came from, which is where its
+/// acknowledgement is sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    Osc,
+    WebSocket,
 }
 
 pub struct ToioScanner {
@@ -425,8 +519,9 @@ pub struct ToioReceiver {
 
 pub struct ToioPeripheral {
     pub name: String,
-    peripheral: platform::Peripheral,
+    pub(crate) peripheral: platform::Peripheral,
     pub peripheral_id: platform::PeripheralId,
+    next_control: AtomicU8,
 }
 
 pub struct Toio {
@@ -435,18 +530,58 @@ pub struct Toio {
     pub id: String,
     pub connected: bool,
     pub channel: Option<JoinHandle<()>>,
-    pub battery: Arc<RwLock<Option<u8>>>,
     pub last_update: Arc<RwLock<Option<SystemTime>>>,
     pub last_command: Arc<RwLock<Option<SystemTime>>>,
+    pub pending_targets: Arc<RwLock<HashMap<u8, (u32, Origin)>>>,
+    pub queued_targets: Arc<RwLock<TargetQueue>>,
+    pub controller: Arc<RwLock<Option<Controller>>>,
+    pub decode_errors: Arc<RwLock<usize>>,
+    pub odometry: Arc<RwLock<Odometry>>,
+    pub state: Arc<RwLock<CubeState>>,
+}
+
+impl Controller {
+    /// Works out how to drive from the position of the toio in mat units and
+    /// its angle in degrees
+    pub fn update(&mut self, x: f32, y: f32, theta: f32) -> FollowStep {
+        return match self {
+            Controller::Path(follower) => follower.update(x, y, theta),
+            Controller::GoTo(goto) => goto.update(x, y, theta),
+        };
+    }
 }
 
 impl Updates {
-    fn new(receiver: Receiver<Update>) -> Updates {
-        return Updates { receiver };
+    fn new(receiver: Receiver<Result<Update, DecodeError>>) -> Updates {
+        return Updates {
+            receiver,
+            errors: 0,
+        };
     }
 
+    /// Waits for the next update, skipping notifications that fail to decode
     pub async fn next(&mut self) -> Option<Update> {
-        return self.receiver.recv().await;
+        while let Some(result) = self.next_result().await {
+            if let Ok(update) = result {
+                return Some(update);
+            }
+        }
+        return None;
+    }
+
+    /// Waits for the next notification, which is either an update or the
+    /// error from decoding it
+    pub async fn next_result(&mut self) -> Option<Result<Update, DecodeError>> {
+        let result = self.receiver.recv().await;
+        if let Some(Err(_)) = result {
+            self.errors += 1;
+        }
+        return result;
+    }
+
+    /// Number of notifications that have failed to decode so far
+    pub fn errors(&self) -> usize {
+        return self.errors;
     }
 }
 
@@ -583,12 +718,12 @@ impl ToioScanner {
     ) -> bool {
         if let Some(properties) = peripheral.properties().await.unwrap() {
             let fullname = properties.local_name.unwrap_or("".to_string());
-            if peripheral.is_connected().await.unwrap() || !fullname.contains(&"toio") {
+            if peripheral.is_connected().await.unwrap() || !fullname.contains("toio") {
                 return false;
             }
 
             let name: Vec<&str> = fullname.split('-').collect();
-            let toio_name = name.last().unwrap_or(&&" ").to_string();
+            let toio_name = name.last().unwrap_or(&" ").to_string();
             if let Some(filter_list) = filter {
                 if !filter_list.contains(&toio_name) {
                     return false;
@@ -627,18 +762,24 @@ impl ToioReceiver {
 }
 
 impl ToioPeripheral {
+    /// Returns a new control ID to identify the response to a target command
+    pub fn control_id(&self) -> u8 {
+        return self.next_control.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn new(name: String, peripheral: platform::Peripheral) -> ToioPeripheral {
         ToioPeripheral {
             name,
             peripheral_id: peripheral.id(),
             peripheral,
+            next_control: AtomicU8::new(0),
         }
     }
 
     pub async fn connect(&self) -> bool {
-        if let Err(_) = self.peripheral.connect().await {
-            return false;
-        } else if let Err(_) = self.peripheral.discover_services().await {
+        if self.peripheral.connect().await.is_err()
+            || self.peripheral.discover_services().await.is_err()
+        {
             return false;
         }
 
@@ -670,8 +811,8 @@ impl ToioPeripheral {
             .await
             {
                 if let Some(event) = possible_event {
-                    if let Some(update) = ToioPeripheral::get_update(event) {
-                        tx.send(update).await.unwrap();
+                    if tx.send(decode(event.uuid, &event.value)).await.is_err() {
+                        break;
                     }
                 }
             }
@@ -680,318 +821,14 @@ impl ToioPeripheral {
         return Ok(Updates::new(rx));
     }
 
-    fn get_update(notification: ValueNotification) -> Option<Update> {
-        let vals = notification.value;
-        match notification.uuid {
-            POSITION => match vals[0] {
-                0x01 => Some(Update::Position {
-                    x_center: vals[1] as u16 | (vals[2] as u16) << 8,
-                    y_center: vals[3] as u16 | (vals[4] as u16) << 8,
-                    theta: vals[5] as u16 | (vals[6] as u16) << 8,
-                    x_sensor: vals[7] as u16 | (vals[8] as u16) << 8,
-                    y_sensor: vals[9] as u16 | (vals[10] as u16) << 8,
-                }),
-                0x02 => Some(Update::Standard {
-                    standard: vals[1] as u32
-                        | (vals[2] as u32) << 8
-                        | (vals[3] as u32) << 16
-                        | (vals[4] as u32) << 24,
-                    theta: vals[5] as u16 | (vals[6] as u16) << 8,
-                }),
-                0x03 => Some(Update::PositionMissed),
-                0x04 => Some(Update::StandardMissed),
-                _ => {
-                    println!(
-                        "Unkown {} Update: {:?}",
-                        uuid_to_string(notification.uuid),
-                        vals
-                    );
-                    None
-                }
-            },
-            MOTOR => match vals[0] {
-                0x83 => Some(Update::MotorTargetResponse {
-                    control: vals[1],
-                    response: vals[2],
-                }),
-                0x84 => Some(Update::MultiTargetResponse {
-                    control: vals[1],
-                    response: vals[2],
-                }),
-                0xe0 => Some(Update::MotorSpeed {
-                    left_speed: vals[1],
-                    right_speed: vals[2],
-                }),
-                _ => {
-                    println!(
-                        "Unkown {} Update: {:?}",
-                        uuid_to_string(notification.uuid),
-                        vals
-                    );
-                    None
-                }
-            },
-            MOTION => match vals[0] {
-                0x01 => Some(Update::Motion {
-                    horizontal: vals[1],
-                    collision: vals[2],
-                    double_tap: vals[3],
-                    posture: vals[4],
-                    shake: vals[5],
-                }),
-                // 0x02 => Some(Update::Magnetic {
-                //     state: vals[1],
-                //     strength: vals[2],
-                //     forcex: vals[3] as i8,
-                //     forcey: vals[4] as i8,
-                //     forcez: vals[5] as i8,
-                // }),
-                // 0x03 => match vals[1] {
-                //     0x01 => Some(Update::PostureEuler {
-                //         roll: vals[2] as u16 | (vals[3] as u16) << 8,
-                //         pitch: vals[4] as u16 | (vals[5] as u16) << 8,
-                //         yaw: vals[5] as u16 | (vals[6] as u16) << 8,
-                //     }),
-                //     0x02 => Some(Update::PostureQuaternion {
-                //         w: 0.0,
-                //         // vals[2] as f32
-                //         //     | (vals[3] as f32) << 8
-                //         //     | (vals[4] as f32) << 16
-                //         //     | (vals[5] as f32) << 24,
-                //         x: 0.0,
-                //         // vals[6] as f32
-                //         //     | (vals[7] as f32) << 8
-                //         //     | (vals[8] as f32) << 16
-                //         //     | (vals[9] as f32) << 24,
-                //         y: 0.0,
-                //         // vals[10] as f32
-                //         //     | (vals[11] as f32) << 8
-                //         //     | (vals[12] as f32) << 16
-                //         //     | (vals[13] as f32) << 24,
-                //         z: 0.0,
-                //         // vals[14] as f32
-                //         //     | (vals[15] as f32) << 8
-                //         //     | (vals[16] as f32) << 16
-                //         //     | (vals[17] as f32) << 24,
-                //     }),
-                //     0x03 => Some(Update::PostureHighPrecisionEuler {
-                //         roll: 0.0,
-                //         // vals[2] as f32
-                //         //     | (vals[3] as f32) << 8
-                //         //     | (vals[4] as f32) << 16
-                //         //     | (vals[5] as f32) << 24,
-                //         pitch: 0.0,
-                //         // vals[6] as f32
-                //         //     | (vals[7] as f32) << 8
-                //         //     | (vals[8] as f32) << 16
-                //         //     | (vals[9] as f32) << 24,
-                //         yaw: 0.0,
-                //         // vals[10] as f32
-                //         //     | (vals[11] as f32) << 8
-                //         //     | (vals[12] as f32) << 16
-                //         //     | (vals[13] as f32) << 24,
-                //     }),
-                //     _ => {
-                //         println!(
-                //             "Unkown {} Update: {:?}",
-                //             uuid_to_string(notification.uuid),
-                //             vals
-                //         );
-                //         None
-                //     }
-                // },
-                _ => {
-                    println!(
-                        "Unkown {} Update: {:?}",
-                        uuid_to_string(notification.uuid),
-                        vals
-                    );
-                    None
-                }
-            },
-            BATTERY => Some(Update::Battery { level: vals[0] }),
-            BUTTON => Some(Update::Button {
-                pressed: vals[1] == 0x80,
-            }),
-            _ => {
-                println!(
-                    "Unkown {} Update: {:?}",
-                    uuid_to_string(notification.uuid),
-                    vals
-                );
-                None
-            }
-        }
-    }
-
-    pub async fn send_command(&self, command: Command) {
-        let uuid = match command {
-            Command::MotionRequest | Command::MagneticRequest | Command::PostureRequest { .. } => {
-                MOTION
-            }
-            Command::MotorControl { .. }
-            | Command::MotorDuration { .. }
-            | Command::MotorTarget { .. }
-            | Command::MultiTarget { .. }
-            | Command::MotorAcceleration { .. } => MOTOR,
-            Command::LedOff | Command::Led { .. } | Command::MultiLed { .. } => LIGHT,
-            Command::SoundOff | Command::Sound { .. } | Command::Midi { .. } => SOUND,
-        };
-
-        let (response_flag, response_type) = match uuid {
-            LIGHT | SOUND => (CharPropFlags::WRITE, WriteType::WithResponse),
-            _ => (
-                CharPropFlags::WRITE_WITHOUT_RESPONSE,
-                WriteType::WithoutResponse,
-            ),
-        };
-
-        let cmd: Vec<u8> = match command {
-            Command::MotionRequest => {
-                vec![0x81]
-            }
-            Command::MagneticRequest => {
-                vec![0x82]
-            }
-            Command::PostureRequest { format } => {
-                vec![0x83, format]
-            }
-            Command::MotorControl {
-                left_direction,
-                left_speed,
-                right_direction,
-                right_speed,
-            } => {
-                vec![
-                    0x01,
-                    0x01,
-                    left_direction,
-                    left_speed,
-                    0x02,
-                    right_direction,
-                    right_speed,
-                ]
-            }
-            Command::MotorDuration {
-                left_direction,
-                left_speed,
-                right_direction,
-                right_speed,
-                duration,
-            } => {
-                vec![
-                    0x02,
-                    0x01,
-                    left_direction,
-                    left_speed,
-                    0x02,
-                    right_direction,
-                    right_speed,
-                    duration,
-                ]
-            }
-            Command::MotorTarget {
-                control,
-                timeout,
-                move_type,
-                max_speed,
-                speed_change,
-                x_target,
-                y_target,
-                theta_target,
-            } => {
-                vec![
-                    0x03,
-                    control,
-                    timeout,
-                    move_type,
-                    max_speed,
-                    speed_change,
-                    0x00,
-                    (x_target & 0x00FF) as u8,
-                    ((x_target & 0xFF00) >> 8) as u8,
-                    (y_target & 0x00FF) as u8,
-                    ((y_target & 0xFF00) >> 8) as u8,
-                    (theta_target & 0x00FF) as u8,
-                    ((theta_target & 0xFF00) >> 8) as u8,
-                ]
-            }
-            Command::MultiTarget {
-                control,
-                timeout,
-                move_type,
-                max_speed,
-                speed_change,
-                op_add,
-                targets,
-            } => {
-                let mut cmd = vec![
-                    0x04,
-                    control,
-                    timeout,
-                    move_type,
-                    max_speed,
-                    speed_change,
-                    0x00,
-                    op_add,
-                ];
-                cmd.append(&mut parse_target_command(targets));
-                cmd
-            }
-
-            Command::MotorAcceleration {
-                velocity,
-                acceleration,
-                rotational_velocity,
-                rotational_direction,
-                direction,
-                priority,
-                duration,
-            } => {
-                vec![
-                    0x05,
-                    velocity,
-                    acceleration,
-                    (rotational_velocity & 0x00FF) as u8,
-                    ((rotational_velocity & 0xFF00) >> 8) as u8,
-                    rotational_direction,
-                    direction,
-                    priority,
-                    duration,
-                ]
-            }
-            Command::LedOff => {
-                vec![0x01]
-            }
-            Command::Led {
-                duration,
-                red,
-                green,
-                blue,
-            } => {
-                vec![0x03, duration, 0x01, 0x01, red, green, blue]
-            }
-            Command::MultiLed {
-                repetitions,
-                lights,
-            } => parse_led_command(repetitions, lights),
-            Command::SoundOff => {
-                vec![0x01]
-            }
-            Command::Sound {
-                sound_effect,
-                volume,
-            } => {
-                vec![
-                    0x02,         //sound
-                    sound_effect, //sound effect ID
-                    volume,       //volume
-                ]
-            }
-            Command::Midi { repetitions, notes } => parse_midi_command(repetitions, notes),
+    pub async fn send_command(&self, command: Command) -> Result<(), btleplug::Error> {
+        let (uuid, cmd, response_type) = encode(command);
+        let response_flag = match response_type {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
         };
 
-        self.write(uuid, cmd, response_flag, response_type).await;
+        return self.write(uuid, cmd, response_flag, response_type).await;
     }
 
     pub async fn write(
@@ -1000,18 +837,18 @@ impl ToioPeripheral {
         cmd: Vec<u8>,
         response_flag: CharPropFlags,
         response_type: WriteType,
-    ) {
+    ) -> Result<(), btleplug::Error> {
         let characteristic = Characteristic {
-            uuid: uuid,
+            uuid,
             service_uuid: SERVICE,
             properties: response_flag,
         };
 
         // println!("{} : {:?}", uuid_to_string(uuid), cmd);
-        self.peripheral
+        return self
+            .peripheral
             .write(&characteristic, &cmd, response_type)
-            .await
-            .unwrap();
+            .await;
     }
 }
 
@@ -1026,10 +863,15 @@ impl Toio {
             },
             connected: true,
             channel: None,
-            battery: Arc::new(RwLock::new(None)),
             toio,
             last_update: Arc::new(RwLock::new(None)),
             last_command: Arc::new(RwLock::new(None)),
+            pending_targets: Arc::new(RwLock::new(HashMap::new())),
+            queued_targets: Arc::new(RwLock::new(TargetQueue::new())),
+            controller: Arc::new(RwLock::new(None)),
+            decode_errors: Arc::new(RwLock::new(0)),
+            odometry: Arc::new(RwLock::new(Odometry::new())),
+            state: Arc::new(RwLock::new(CubeState::new())),
         };
     }
 
@@ -1044,10 +886,6 @@ impl Toio {
         self.connected = false;
     }
 
-    pub fn get_battery(&self) -> Arc<RwLock<Option<u8>>> {
-        return self.battery.clone();
-    }
-
     pub fn get_last_update(&self) -> Arc<RwLock<Option<SystemTime>>> {
         return self.last_update.clone();
     }
@@ -1056,68 +894,40 @@ impl Toio {
         return self.last_command.clone();
     }
 
-    pub async fn is_connected(&self) -> bool {
-        return self.connected;
+    /// Sequence numbers of target commands that are waiting on a
+    /// response from the toio, with where each came from, keyed by their
+    /// control ID
+    pub fn get_pending_targets(&self) -> Arc<RwLock<HashMap<u8, (u32, Origin)>>> {
+        return self.pending_targets.clone();
     }
-}
 
-/// matches UUIDs of toios a string of their coresponding service
-pub fn uuid_to_string(uuid: Uuid) -> String {
-    return match uuid {
-        SERVICE => "Service",
-        POSITION => "Position",
-        MOTOR => "Motor",
-        LIGHT => "Light",
-        SOUND => "Sound",
-        MOTION => "Motion",
-        BUTTON => "Button",
-        BATTERY => "Battery",
-        CONFIG => "Config",
-        _ => "",
+    /// Parts of a long MultiTarget command that have not been sent yet
+    pub fn get_queued_targets(&self) -> Arc<RwLock<TargetQueue>> {
+        return self.queued_targets.clone();
     }
-    .to_owned();
-}
-
-fn parse_target_command(vals: Vec<TargetCommand>) -> Vec<u8> {
-    let mut cmd = vec![];
 
-    for target in vals.iter() {
-        cmd.push((target.x_target & 0x00FF) as u8);
-        cmd.push(((target.x_target & 0xFF00) >> 8) as u8);
-        cmd.push((target.y_target & 0x00FF) as u8);
-        cmd.push(((target.y_target & 0xFF00) >> 8) as u8);
-        cmd.push((target.theta_target & 0x00FF) as u8);
-        cmd.push(((target.theta_target & 0xFF00) >> 8) as u8);
+    /// Controller steering the toio from the host, which is updated each time
+    /// the position of the toio is updated
+    pub fn get_controller(&self) -> Arc<RwLock<Option<Controller>>> {
+        return self.controller.clone();
     }
 
-    return cmd;
-}
-
-fn parse_led_command(repetitions: u8, vals: Vec<LedCommand>) -> Vec<u8> {
-    let mut cmd = vec![0x04, repetitions, vals.len() as u8];
-
-    for led in vals.iter() {
-        cmd.push(led.duration);
-        cmd.push(0x01);
-        cmd.push(0x01);
-        cmd.push(led.red);
-        cmd.push(led.green);
-        cmd.push(led.blue);
+    /// Number of notifications from the toio that failed to decode
+    pub fn get_decode_errors(&self) -> Arc<RwLock<usize>> {
+        return self.decode_errors.clone();
     }
 
-    return cmd;
-}
-
-fn parse_midi_command(repetitions: u8, vals: Vec<MidiCommand>) -> Vec<u8> {
-    let mut cmd = vec![0x03, repetitions, vals.len() as u8];
+    pub fn get_odometry(&self) -> Arc<RwLock<Odometry>> {
+        return self.odometry.clone();
+    }
 
-    for note in vals.iter() {
-        cmd.push(note.duration);
-        cmd.push(note.note);
-        cmd.push(note.volume);
+    pub fn get_state(&self) -> Arc<RwLock<CubeState>> {
+        return self.state.clone();
     }
 
-    return cmd;
+    pub async fn is_connected(&self) -> bool {
+        return self.connected;
+    }
 }
 
 fn return_toio_id(name: &str) -> Option<usize> {
@@ -1,9 +1,10 @@
 use std::error::Error;
-use std::io::stdout;
+use std::io::{self, stdout};
 use std::vec;
 
 use crossterm::{
     cursor::Show,
+    event::{self, Event, KeyCode},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
@@ -15,9 +16,26 @@ use ratatui::{
 
 pub type ToioUI = Option<Terminal<CrosstermBackend<std::io::Stdout>>>;
 
+/// Row of the table for a toio, with each column already formatted
+pub struct ToioInfo {
+    pub name: String,
+    pub id: String,
+    pub battery: String,
+    pub last_update: String,
+    pub last_command: String,
+    pub connected: bool,
+    /// Number of notifications that failed to decode
+    pub errors: String,
+    pub odometry: String,
+    pub position: String,
+}
+
+/// Draws a table of the toios, with the errors the OSC server has had, such as
+/// "0 send, 0 receive, 0 rejected", at the bottom
 pub fn ui(
-    toio_info: Vec<(String, String, String, String, String, bool)>,
+    toio_info: Vec<ToioInfo>,
     filter: Option<Vec<usize>>,
+    server_errors: String,
 ) -> impl Fn(&mut Frame) {
     return move |frame| {
         let area = frame.size();
@@ -26,12 +44,12 @@ pub fn ui(
             .iter()
             .enumerate()
             .map(|(i, val)| {
-                let connected_color = match val.5 {
+                let connected_color = match val.connected {
                     true => Style::new().white(),
                     false => Style::new().red(),
                 };
 
-                let battery = val.2.clone();
+                let battery = val.battery.clone();
                 let battery_color = if let Ok(level) = battery.parse::<i32>() {
                     if level == 10 || connected_color == Style::new().red() {
                         Style::new().red()
@@ -48,11 +66,14 @@ pub fn ui(
 
                 Row::new(vec![
                     Span::raw(format!("{}", i)).style(connected_color),
-                    Span::raw(val.0.clone()).style(connected_color),
-                    Span::raw(val.1.clone()).style(connected_color),
+                    Span::raw(val.name.clone()).style(connected_color),
+                    Span::raw(val.id.clone()).style(connected_color),
                     Span::raw(battery).style(battery_color),
-                    Span::raw(val.3.clone()).style(connected_color),
-                    Span::raw(val.4.clone()).style(connected_color),
+                    Span::raw(val.last_update.clone()).style(connected_color),
+                    Span::raw(val.last_command.clone()).style(connected_color),
+                    Span::raw(val.errors.clone()).style(connected_color),
+                    Span::raw(val.odometry.clone()).style(connected_color),
+                    Span::raw(val.position.clone()).style(connected_color),
                 ])
             })
             .collect();
@@ -66,6 +87,9 @@ pub fn ui(
             Constraint::Length(7),
             Constraint::Length(12),
             Constraint::Length(12),
+            Constraint::Length(6),
+            Constraint::Length(18),
+            Constraint::Length(14),
         ];
 
         let table = Table::new(rows, widths)
@@ -78,13 +102,16 @@ pub fn ui(
                     "Battery",
                     "Last Update",
                     "Last Command",
+                    "Errors",
+                    "Odometry",
+                    "Position",
                 ])
                 .style(Style::new().bold()),
             )
             .highlight_style(Style::new().reversed())
             .highlight_symbol(">>");
 
-        let connected_ids: Vec<String> = toio_info.iter().map(|val| val.1.clone()).collect();
+        let connected_ids: Vec<String> = toio_info.iter().map(|val| val.id.clone()).collect();
         let filter_list = match &filter {
             Some(toio_filter) => {
                 let spans: Vec<Span> = toio_filter
@@ -118,6 +145,11 @@ pub fn ui(
                             .alignment(Alignment::Center)
                             .position(Position::Bottom),
                     )
+                    .title(
+                        Title::from(format!(" OSC errors: {} ", server_errors))
+                            .alignment(Alignment::Right)
+                            .position(Position::Bottom),
+                    )
                     .borders(Borders::ALL),
             ),
             area,
@@ -138,3 +170,14 @@ pub fn exit_terminal() -> Result<(), Box<dyn Error>> {
     stdout().execute(LeaveAlternateScreen)?;
     Ok(())
 }
+
+pub fn handle_events() -> io::Result<bool> {
+    if event::poll(std::time::Duration::from_millis(50))? {
+        if let Event::Key(key) = event::read()? {
+            if key.kind == event::KeyEventKind::Press && key.code == KeyCode::Char('q') {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
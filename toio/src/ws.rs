@@ -0,0 +1,218 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socket2::{Protocol, Type};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+
+use crate::server::*;
+use toio::*;
+
+/// WebSocket server that accepts commands as JSON objects, such as
+/// `{"cube": 3, "cmd": "led", "duration": 0, "red": 255, "green": 0, "blue": 0}`,
+/// and streams every update from the toios back as JSON, using the serde
+/// representation of `Command` and `Update`. Commands are sent to the same
+/// toios as OSC commands with the same ID. `{"cube": 3, "query": "state"}`
+/// asks for the latest `CubeState` of a toio, which is sent back to the same
+/// client only.
+#[derive(Clone)]
+pub struct WsServer {
+    listeners: Vec<Arc<TcpListener>>,
+    updates: broadcast::Sender<String>,
+    shutdown: CancellationToken,
+}
+
+impl WsServer {
+    /// Binds a TCP listener to every address given
+    pub fn bind(addrs: &[SocketAddr]) -> io::Result<WsServer> {
+        let mut listeners = vec![];
+
+        for addr in addrs.iter() {
+            let socket = bind_socket(*addr, Type::STREAM, Protocol::TCP)?;
+            socket.listen(128)?;
+            listeners.push(Arc::new(TcpListener::from_std(socket.into())?));
+        }
+
+        let (updates, _) = broadcast::channel(256);
+
+        return Ok(WsServer {
+            listeners,
+            updates,
+            shutdown: CancellationToken::new(),
+        });
+    }
+
+    /// Starts a task for each listener that accepts WebSocket connections
+    /// until the server is shut down
+    pub fn listen(&self, connected: Connected) -> Vec<JoinHandle<()>> {
+        return self
+            .listeners
+            .iter()
+            .map(|listener| {
+                let server = self.clone();
+                let listener = listener.clone();
+                let connected = connected.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let stream = tokio::select! {
+                            _ = server.shutdown.cancelled() => break,
+                            result = listener.accept() => match result {
+                                Ok((stream, _)) => stream,
+                                Err(err) => {
+                                    eprintln!("Error accepting WebSocket connection: {}", err);
+                                    continue;
+                                }
+                            },
+                        };
+
+                        tokio::spawn(server.clone().serve(stream, connected.clone()));
+                    }
+                })
+            })
+            .collect();
+    }
+
+    /// Reads JSON commands from a WebSocket, and writes every update back to
+    /// it, until either side closes
+    async fn serve(self, stream: TcpStream, connected: Connected) {
+        let websocket = match tokio_tungstenite::accept_async(stream).await {
+            Ok(websocket) => websocket,
+            Err(err) => {
+                eprintln!("Error opening WebSocket: {}", err);
+                return;
+            }
+        };
+
+        let (mut writer, mut reader) = websocket.split();
+        let mut updates = self.updates.subscribe();
+
+        loop {
+            let reply = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                message = reader.next() => match message {
+                    Some(Ok(Message::Text(text))) if query_from_json(&text).is_some() => {
+                        state_json(&connected, &text).await
+                    }
+                    Some(Ok(Message::Text(text))) => match command_from_json(&text) {
+                        Some((toionum, cmd, seq)) => {
+                            let origin = seq.map(|seq| (seq, Origin::WebSocket));
+                            let ack = dispatch(&connected, toionum, cmd, origin).await;
+                            if let Some((seq, status)) = ack {
+                                self.send_ack(toionum, seq, status);
+                            }
+                            continue;
+                        }
+                        None => json!({ "error": "invalid command", "message": text }).to_string(),
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => {
+                        eprintln!("Error reading WebSocket: {}", err);
+                        break;
+                    }
+                },
+                update = updates.recv() => match update {
+                    Ok(update) => update,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+
+            if let Err(err) = writer.send(Message::Text(reply)).await {
+                eprintln!("Error writing WebSocket: {}", err);
+                break;
+            }
+        }
+    }
+
+    /// Sends an update from the toio with the given ID to every client, with
+    /// positions also placed in the world frame of the mat layout
+    pub fn send_update(&self, id: usize, update: &Update, world: Option<WorldPosition>) {
+        // there are no receivers when no clients are connected
+        let _ = self.updates.send(update_to_json(id, update, world));
+    }
+
+    /// Sends the outcome of a command that was sent with a sequence number
+    pub fn send_ack(&self, id: usize, seq: u32, status: i32) {
+        let ack = json!({ "cube": id, "ack": seq, "status": status });
+        let _ = self.updates.send(ack.to_string());
+    }
+
+    /// Stops accepting connections and closes every open WebSocket
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// A command for a toio received over WebSocket, with the ID of the toio and
+/// an optional sequence number to have the command acknowledged
+#[derive(Deserialize)]
+struct Request {
+    cube: usize,
+    seq: Option<u32>,
+    #[serde(flatten)]
+    cmd: Command,
+}
+
+/// A request for the state of a toio received over WebSocket, such as
+/// `{"cube": 3, "query": "state"}`
+#[derive(Deserialize)]
+struct StateQuery {
+    cube: usize,
+    query: String,
+}
+
+/// An update from a toio sent over WebSocket, with the ID of the toio
+#[derive(Serialize)]
+struct Event<'a> {
+    cube: usize,
+    #[serde(flatten)]
+    update: &'a Update,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    world: Option<WorldPosition>,
+}
+
+/// Converts a JSON object into a command for a toio. Besides the fields of the
+/// command, the object holds the toio ID in `cube` and an optional `seq`.
+pub fn command_from_json(json: &str) -> Option<(usize, Command, Option<u32>)> {
+    let request: Request = serde_json::from_str(json).ok()?;
+    return Some((request.cube, request.cmd, request.seq));
+}
+
+/// Converts a JSON request for the state of a toio into the toio ID, which is
+/// answered with the `CubeState` of the toio in `state`
+pub fn query_from_json(json: &str) -> Option<usize> {
+    let query: StateQuery = serde_json::from_str(json).ok()?;
+    return (query.query == "state").then_some(query.cube);
+}
+
+/// Answers a JSON request for the state of a toio
+async fn state_json(connected: &Connected, json: &str) -> String {
+    let toionum = query_from_json(json).unwrap_or_default();
+    let Some(toio) = connected.read().await.get(toionum).cloned() else {
+        return json!({ "error": "unknown cube", "message": json }).to_string();
+    };
+    let state = toio.read().await.get_state();
+    let state = state.read().await.clone();
+    return json!({ "cube": toionum, "state": state }).to_string();
+}
+
+/// Converts an update from a toio into a JSON object, with the toio ID in `cube`
+/// and, for a position, where it is in the world frame in `world`
+pub fn update_to_json(id: usize, update: &Update, world: Option<WorldPosition>) -> String {
+    let world = world.filter(|_| matches!(update, Update::Position { .. }));
+    return serde_json::to_string(&Event {
+        cube: id,
+        update,
+        world,
+    })
+    .unwrap_or_default();
+}